@@ -0,0 +1,19 @@
+/// Options that change how [`UpsPatch::apply_with_options`](crate::UpsPatch::apply_with_options)
+/// checks the files and the patch
+///
+/// The default options are the checks [`UpsPatch::apply`](crate::UpsPatch::apply) makes.
+/// # Examples
+/// ```
+/// # use ups::ApplyOptions;
+/// let options = ApplyOptions { strict: true, ..ApplyOptions::default() };
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ApplyOptions {
+    /// Rejects patches with hunks that change bytes past the end of both the source and the
+    /// target files with [`OutOfBounds`](crate::ApplyError::OutOfBounds), instead of ignoring
+    /// those bytes
+    pub strict: bool,
+    /// When the given file isn't the source file, tries removing or adding the headers some ROM
+    /// dumps have, see [`UpsPatch::apply_with_fixup`](crate::UpsPatch::apply_with_fixup)
+    pub fix_headers: bool,
+}
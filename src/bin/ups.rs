@@ -0,0 +1,146 @@
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::process;
+
+use ups::{load_any, ApplyError, FileVerdict, LoadError, UpsError, UpsPatch};
+
+const USAGE: &str = "Usage:
+    ups apply <source> <patch> <output>    Apply a patch to the source file
+    ups create <source> <target> <patch>   Create a UPS patch from source to target
+    ups revert <target> <patch> <output>   Get the source file back from the target file
+    ups invert <patch> <output>            Create the patch that goes from the target file to the source file
+    ups info <patch>                       Show the contents of a UPS patch
+    ups info <patch> --hunks [<source>]    Also show the bytes changed by each hunk
+    ups verify <file> <patch>              Check if a file is the source or target of a UPS patch
+
+Any file can be given as - to use stdin or stdout instead.
+
+Exit codes:
+    0  Success
+    1  Wrong arguments
+    2  A file couldn't be read or written
+    3  The patch couldn't be loaded
+    4  The file doesn't match the patch
+    5  The patched file doesn't match the patch
+    6  Any other error";
+
+const EXIT_USAGE: i32 = 1;
+const EXIT_IO: i32 = 2;
+const EXIT_LOAD: i32 = 3;
+const EXIT_SOURCE_MISMATCH: i32 = 4;
+const EXIT_TARGET_MISMATCH: i32 = 5;
+const EXIT_OTHER: i32 = 6;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["apply", source, patch, output] => apply(source, patch, output),
+        ["create", source, target, patch] => create(source, target, patch),
+        ["revert", target, patch, output] => revert(target, patch, output),
+        ["invert", patch, output] => invert(patch, output),
+        ["info", patch] => info(patch, false, None),
+        ["info", patch, "--hunks"] => info(patch, true, None),
+        ["info", patch, "--hunks", source] => info(patch, true, Some(source)),
+        ["verify", file, patch] => verify(file, patch),
+        ["help"] | ["--help"] | ["-h"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(exit_code(&error));
+    }
+}
+
+fn exit_code(error: &UpsError) -> i32 {
+    match error {
+        UpsError::Io(_) => EXIT_IO,
+        UpsError::Load(LoadError::Unknown) => EXIT_OTHER,
+        UpsError::Load(_) => EXIT_LOAD,
+        UpsError::Apply(ApplyError::SourceMismatch { .. })
+        | UpsError::Apply(ApplyError::SourceSizeMismatch { .. }) => EXIT_SOURCE_MISMATCH,
+        UpsError::Apply(ApplyError::TargetMismatch { .. }) => EXIT_TARGET_MISMATCH,
+        _ => EXIT_OTHER,
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, UpsError> {
+    let mut content: Vec<u8> = vec![];
+    if path == "-" {
+        io::stdin().lock().read_to_end(&mut content)?;
+    } else {
+        content = fs::read(path)?;
+    }
+    Ok(content)
+}
+
+fn write_file(path: &str, content: &[u8]) -> Result<(), UpsError> {
+    if path == "-" {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(content)?;
+        stdout.flush()?;
+    } else {
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+fn apply(source_path: &str, patch_path: &str, output_path: &str) -> Result<(), UpsError> {
+    let patch = load_any(&read_file(patch_path)?)?;
+    let source = read_file(source_path)?;
+    write_file(output_path, &patch.apply(&source)?)
+}
+
+fn create(source_path: &str, target_path: &str, patch_path: &str) -> Result<(), UpsError> {
+    let source = read_file(source_path)?;
+    let target = read_file(target_path)?;
+    let mut patch: Vec<u8> = vec![];
+    UpsPatch::create_stream(&mut Cursor::new(&source), &mut Cursor::new(&target), &mut patch)?;
+    write_file(patch_path, &patch)
+}
+
+fn revert(target_path: &str, patch_path: &str, output_path: &str) -> Result<(), UpsError> {
+    let patch = UpsPatch::load(&read_file(patch_path)?)?;
+    let target = read_file(target_path)?;
+    write_file(output_path, &patch.revert(&target)?)
+}
+
+fn invert(patch_path: &str, output_path: &str) -> Result<(), UpsError> {
+    let patch = UpsPatch::load(&read_file(patch_path)?)?;
+    write_file(output_path, &patch.invert().get_patch_file_contents())
+}
+
+fn info(patch_path: &str, hunks: bool, source_path: Option<&str>) -> Result<(), UpsError> {
+    let patch = UpsPatch::load(&read_file(patch_path)?)?;
+    println!("{}", patch.summary());
+    if hunks {
+        let source = source_path.map(read_file).transpose()?;
+        println!();
+        print!("{}", patch.dump(source.as_deref()));
+    }
+    Ok(())
+}
+
+fn verify(file_path: &str, patch_path: &str) -> Result<(), UpsError> {
+    let patch = UpsPatch::load(&read_file(patch_path)?)?;
+    let content = read_file(file_path)?;
+    match patch.check_source_reader(&content[..])? {
+        FileVerdict::Source => println!("{} is the source file of the patch", file_path),
+        FileVerdict::Target => println!("{} is the target file of the patch", file_path),
+        FileVerdict::SizeMismatch { expected, actual } => {
+            return Err(UpsError::Apply(ApplyError::SourceSizeMismatch { expected, actual }));
+        }
+        FileVerdict::Crc32Mismatch { expected, actual } => {
+            return Err(UpsError::Apply(ApplyError::SourceMismatch { expected, actual }));
+        }
+    }
+    Ok(())
+}
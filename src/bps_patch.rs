@@ -0,0 +1,376 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+
+use crate::{
+    crc32,
+    varint,
+    UpsError,
+    LoadError::*,
+    ApplyError::*,
+};
+
+/// A single action of a BPS patch, each one writes the next `length` bytes of the final file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BpsAction {
+    /// Copies bytes from the source file, from the same position they have in the final file
+    SourceRead {
+        length: u64,
+    },
+    /// Writes the given bytes
+    TargetRead {
+        data: Vec<u8>,
+    },
+    /// Copies bytes from anywhere in the source file.
+    /// `offset` is relative to where the previous `SourceCopy` ended
+    SourceCopy {
+        length: u64,
+        offset: i64,
+    },
+    /// Copies bytes from the part of the final file that has already been written.
+    /// `offset` is relative to where the previous `TargetCopy` ended
+    TargetCopy {
+        length: u64,
+        offset: i64,
+    },
+}
+
+///Represents a bps patch
+///
+/// Unlike UPS patches, BPS patches can copy data from anywhere in the source or in the already
+/// written part of the target, so data that has been moved around doesn't make the patch bigger.
+#[derive(Debug, Eq, PartialEq)]
+pub struct BpsPatch {
+    ///The file size of the original file
+    pub source_file_size: u64,
+    ///The file size of the final file
+    pub target_file_size: u64,
+    /// Free form data stored along the patch, usually an XML document describing it
+    pub metadata: Vec<u8>,
+    /// The actions that write the final file, in order
+    pub actions: Vec<BpsAction>,
+    /// The crc32 checksum of the source file
+    pub source_crc32: u32,
+    /// The crc32 checksum of the final file
+    pub target_crc32: u32,
+    /// The crc 32 checksum of the patch file itself
+    pub patch_crc32: u32,
+}
+
+impl BpsPatch {
+    pub(crate) const CANON_HEADER: [u8; 4] = *b"BPS1";
+    /// Matches shorter than this are written as they are, as copying them would take more space
+    const MIN_MATCH: usize = 4;
+
+    /// Creates a Patch from the given source and target files
+    /// # Arguments
+    /// * `source_content` - The contents of the source/original file
+    /// * `target_content` - The contents of the target/final file
+    /// * `metadata` - Data to store along the patch, can be empty
+    ///
+    pub fn create(source_content: &[u8], target_content: &[u8], metadata: &[u8]) -> BpsPatch {
+        let mut source_index: HashMap<[u8; 4], usize> = HashMap::new();
+        for i in (0..source_content.len().saturating_sub(3)).rev() {
+            source_index.insert(BpsPatch::key(source_content, i), i);
+        }
+        let mut target_index: HashMap<[u8; 4], usize> = HashMap::new();
+        let mut target_indexed = 0;
+
+        let mut actions: Vec<BpsAction> = vec![];
+        let mut pending_data: Vec<u8> = vec![];
+        let mut source_relative_offset = 0;
+        let mut target_relative_offset = 0;
+        let mut i = 0;
+        while i < target_content.len() {
+            let target_rest = &target_content[i..];
+            let source_read_length = if i < source_content.len() {
+                BpsPatch::common_length(&source_content[i..], target_rest)
+            } else { 0 };
+            let (source_copy_offset, source_copy_length) = if target_rest.len() >= 4 {
+                source_index.get(&BpsPatch::key(target_content, i))
+                    .map_or((0, 0), |&offset| (offset, BpsPatch::common_length(&source_content[offset..], target_rest)))
+            } else { (0, 0) };
+            let (target_copy_offset, target_copy_length) = if target_rest.len() >= 4 {
+                target_index.get(&BpsPatch::key(target_content, i))
+                    .map_or((0, 0), |&offset| (offset, BpsPatch::common_length(&target_content[offset..], target_rest)))
+            } else { (0, 0) };
+
+            let best_length = source_read_length.max(source_copy_length).max(target_copy_length);
+            if best_length < BpsPatch::MIN_MATCH {
+                pending_data.push(target_content[i]);
+                i += 1;
+            } else {
+                if !pending_data.is_empty() {
+                    actions.push(BpsAction::TargetRead { data: pending_data });
+                    pending_data = vec![];
+                }
+                if source_read_length == best_length {
+                    actions.push(BpsAction::SourceRead { length: best_length as u64 });
+                } else if source_copy_length == best_length {
+                    actions.push(BpsAction::SourceCopy {
+                        length: best_length as u64,
+                        offset: source_copy_offset as i64 - source_relative_offset as i64,
+                    });
+                    source_relative_offset = source_copy_offset + best_length;
+                } else {
+                    actions.push(BpsAction::TargetCopy {
+                        length: best_length as u64,
+                        offset: target_copy_offset as i64 - target_relative_offset as i64,
+                    });
+                    target_relative_offset = target_copy_offset + best_length;
+                }
+                i += best_length;
+            }
+            while target_indexed < i && target_indexed + 4 <= target_content.len() {
+                target_index.insert(BpsPatch::key(target_content, target_indexed), target_indexed);
+                target_indexed += 1;
+            }
+        }
+        if !pending_data.is_empty() {
+            actions.push(BpsAction::TargetRead { data: pending_data });
+        }
+
+        let mut patch = BpsPatch {
+            source_file_size: source_content.len() as u64,
+            target_file_size: target_content.len() as u64,
+            metadata: metadata.to_vec(),
+            actions,
+            source_crc32: crc32::calculate(source_content),
+            target_crc32: crc32::calculate(target_content),
+            patch_crc32: 0,
+        };
+        patch.patch_crc32 = crc32::calculate(&patch.tailless_bytearray());
+        patch
+    }
+
+    fn key(content: &[u8], i: usize) -> [u8; 4] {
+        content[i..i + 4].try_into().unwrap()
+    }
+
+    fn common_length(a: &[u8], b: &[u8]) -> usize {
+        a.iter().zip(b).take_while(|(x, y)| x == y).count()
+    }
+
+    /// Loads an already existing patch, if the given file contents don't contain a valid BPS patch returns a UpsError
+    /// # Arguments
+    /// * `content` - The content of the patch file to load
+    pub fn load(content: &[u8]) -> Result<BpsPatch, UpsError> {
+        if !content.starts_with(&BpsPatch::CANON_HEADER) {
+            return Err(UpsError::Load(IsNotBpsFile));
+        }
+        let l = content.len();
+        if l < BpsPatch::CANON_HEADER.len() + 12 {
+            return Err(UpsError::Load(Truncated { offset: 0 }));
+        }
+        let patch_crc32 = u32::from_le_bytes(content[l - 4..l].try_into().unwrap());
+        let patch_computed_crc32 = crc32::calculate(&content[0..l - 4]);
+        if patch_computed_crc32 != patch_crc32 {
+            return Err(UpsError::Load(IsCorrupted { expected: patch_crc32, actual: patch_computed_crc32 }));
+        }
+        let source_crc32 = u32::from_le_bytes(content[l - 12..l - 8].try_into().unwrap());
+        let target_crc32 = u32::from_le_bytes(content[l - 8..l - 4].try_into().unwrap());
+
+        let body = &content[..l - 12];
+        let read = |i: usize| varint::decode(body, i).map_err(UpsError::Load);
+        let take = |i: usize, length: u64| {
+            body.get(i..i.saturating_add(length as usize)).ok_or(UpsError::Load(Truncated { offset: i as u64 }))
+        };
+
+        let i = BpsPatch::CANON_HEADER.len();
+        let (source_file_size, i) = read(i)?;
+        let (target_file_size, i) = read(i)?;
+        let (metadata_size, i) = read(i)?;
+        let metadata = take(i, metadata_size)?.to_vec();
+        let mut i = i + metadata_size as usize;
+
+        let mut actions: Vec<BpsAction> = vec![];
+        while i < body.len() {
+            let (data, next_i) = read(i)?;
+            i = next_i;
+            let length = (data >> 2) + 1;
+            let action = match data & 3 {
+                0 => BpsAction::SourceRead { length },
+                1 => {
+                    let data = take(i, length)?.to_vec();
+                    i += length as usize;
+                    BpsAction::TargetRead { data }
+                }
+                command => {
+                    let (offset, next_i) = read(i)?;
+                    i = next_i;
+                    let offset = if offset & 1 != 0 { -((offset >> 1) as i64) } else { (offset >> 1) as i64 };
+                    if command == 2 {
+                        BpsAction::SourceCopy { length, offset }
+                    } else {
+                        BpsAction::TargetCopy { length, offset }
+                    }
+                }
+            };
+            actions.push(action);
+        }
+
+        Ok(BpsPatch {
+            source_file_size,
+            target_file_size,
+            metadata,
+            actions,
+            source_crc32,
+            target_crc32,
+            patch_crc32,
+        })
+    }
+
+    /// Given the contents of a file, verifies that it is the expected source for the patch,
+    /// applies the patch and verifies that the output is the expected target for the patch.
+    /// # Arguments
+    /// * `source` - The content of the source file
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        let source_crc32 = crc32::calculate(source);
+        if source_crc32 != self.source_crc32 {
+            return Err(UpsError::Apply(SourceMismatch { expected: self.source_crc32, actual: source_crc32 }))
+        }
+        let target = self.apply_no_check(source)?;
+        let target_crc32 = crc32::calculate(&target);
+        if target_crc32 != self.target_crc32 {
+            return Err(UpsError::Apply(TargetMismatch { expected: self.target_crc32, actual: target_crc32 }))
+        }
+        Ok(target)
+    }
+
+    /// Applies a patch to a given source file contents.
+    /// This function doesn't check for file to actually be the correct source file, it just
+    /// applies the patch, but it still fails if an action copies data from outside the files or
+    /// the actions don't produce a file of the target size.
+    ///
+    /// The target size comes from the patch, so memory is only reserved upfront for what the
+    /// source and the data in the patch can fill, and the rest is reserved fallibly as the
+    /// actions need it, returning an error instead of aborting when it is too much.
+    pub fn apply_no_check(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        let patch_data: u64 = self.actions.iter()
+            .map(|action| if let BpsAction::TargetRead { data } = action { data.len() as u64 } else { 0 })
+            .sum();
+        let capacity = min(self.target_file_size, (source.len() as u64).saturating_add(patch_data));
+        let mut output: Vec<u8> = Vec::with_capacity(capacity as usize);
+        let mut source_relative_offset: i64 = 0;
+        let mut target_relative_offset: i64 = 0;
+        for action in &self.actions {
+            let length = match action {
+                BpsAction::SourceRead { length }
+                | BpsAction::SourceCopy { length, .. }
+                | BpsAction::TargetCopy { length, .. } => *length,
+                BpsAction::TargetRead { data } => data.len() as u64,
+            };
+            let position = output.len() as u64;
+            let out_of_bounds = || UpsError::Apply(OutOfBounds { offset: position });
+            if length > self.target_file_size - output.len() as u64 {
+                return Err(out_of_bounds());
+            }
+            usize::try_from(length).ok()
+                .and_then(|length| output.try_reserve(length).ok())
+                .ok_or(UpsError::Apply(TooBig { size: self.target_file_size }))?;
+            match action {
+                BpsAction::SourceRead { .. } => {
+                    let data = BpsPatch::slice(source, output.len() as i64, length).ok_or_else(out_of_bounds)?;
+                    output.extend_from_slice(data);
+                }
+                BpsAction::TargetRead { data } => output.extend_from_slice(data),
+                BpsAction::SourceCopy { offset, .. } => {
+                    source_relative_offset = BpsPatch::move_offset(source_relative_offset, *offset).ok_or_else(out_of_bounds)?;
+                    output.extend_from_slice(BpsPatch::slice(source, source_relative_offset, length).ok_or_else(out_of_bounds)?);
+                    source_relative_offset += length as i64;
+                }
+                BpsAction::TargetCopy { offset, .. } => {
+                    target_relative_offset = BpsPatch::move_offset(target_relative_offset, *offset).ok_or_else(out_of_bounds)?;
+                    if target_relative_offset as usize >= output.len() {
+                        return Err(out_of_bounds());
+                    }
+                    // The copy can overlap the bytes it is writing, so it has to go byte by byte
+                    for _ in 0..length {
+                        output.push(output[target_relative_offset as usize]);
+                        target_relative_offset += 1;
+                    }
+                }
+            }
+        }
+        if output.len() as u64 != self.target_file_size {
+            return Err(UpsError::Apply(TargetSizeMismatch {
+                expected: self.target_file_size,
+                actual: output.len() as u64,
+            }));
+        }
+        Ok(output)
+    }
+
+    fn move_offset(relative_offset: i64, offset: i64) -> Option<i64> {
+        relative_offset.checked_add(offset)
+            .filter(|new_offset| *new_offset >= 0)
+    }
+
+    fn slice(content: &[u8], start: i64, length: u64) -> Option<&[u8]> {
+        let start = start as usize;
+        start.checked_add(length as usize)
+            .and_then(|end| content.get(start..end))
+    }
+
+    fn encode_offset(offset: i64) -> Vec<u8> {
+        varint::encode((offset.unsigned_abs() << 1) | (offset < 0) as u64)
+    }
+
+    fn tailless_bytearray(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+        output.extend(BpsPatch::CANON_HEADER);
+        output.extend(varint::encode(self.source_file_size));
+        output.extend(varint::encode(self.target_file_size));
+        output.extend(varint::encode(self.metadata.len() as u64));
+        output.extend(&self.metadata);
+        // Offsets of the skipped copies, that have to be added to the next copy of their kind
+        let mut source_offset: i64 = 0;
+        let mut target_offset: i64 = 0;
+        for action in &self.actions {
+            let (command, length) = match action {
+                BpsAction::SourceRead { length } => (0, *length),
+                BpsAction::TargetRead { data } => (1, data.len() as u64),
+                BpsAction::SourceCopy { length, .. } => (2, *length),
+                BpsAction::TargetCopy { length, .. } => (3, *length),
+            };
+            match action {
+                BpsAction::SourceCopy { offset, .. } => source_offset = source_offset.saturating_add(*offset),
+                BpsAction::TargetCopy { offset, .. } => target_offset = target_offset.saturating_add(*offset),
+                _ => {}
+            }
+            // The format can't store actions that write nothing, so they are left out, which
+            // doesn't change the result as long as the offset of a copy still gets applied
+            if length == 0 {
+                continue;
+            }
+            output.extend(varint::encode(((length - 1) << 2) | command));
+            match action {
+                BpsAction::TargetRead { data } => output.extend(data),
+                BpsAction::SourceCopy { .. } => output.extend(BpsPatch::encode_offset(std::mem::take(&mut source_offset))),
+                BpsAction::TargetCopy { .. } => output.extend(BpsPatch::encode_offset(std::mem::take(&mut target_offset))),
+                BpsAction::SourceRead { .. } => {}
+            }
+        }
+        output.extend(self.source_crc32.to_le_bytes());
+        output.extend(self.target_crc32.to_le_bytes());
+        output
+    }
+
+    /// Returns a vector with the contents of the patch.bps file, with the patch checksum computed
+    /// from the written bytes, so that it stays valid after changing the patch
+    pub fn get_patch_file_contents(&self) -> Vec<u8> {
+        let mut output = self.tailless_bytearray();
+        output.extend(crc32::calculate(&output).to_le_bytes());
+        output
+    }
+
+    /// Checks if the given file contents matches the source file for the BPS patch
+    pub fn file_is_source(&self, content: &[u8]) -> bool {
+        crc32::calculate(content) == self.source_crc32
+    }
+
+    /// Checks if the given file contents matches the target file for the BPS patch
+    pub fn file_is_target(&self, content: &[u8]) -> bool {
+        crc32::calculate(content) == self.target_crc32
+    }
+}
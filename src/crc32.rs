@@ -1,3 +1,5 @@
+use std::io::{self, Write};
+
 const TABLE: [u32; 256] = [0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f,
     0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988,
     0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2,
@@ -42,15 +44,455 @@ const TABLE: [u32; 256] = [0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076
     0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94,
     0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d];
 
+/// `TABLE` extended for a byte followed by 1 to 15 zero bytes, the table `n` gives what a byte
+/// adds to the checksum when there are `n` more bytes after it, so that 16 bytes can be
+/// processed at once looking up each one in its own table (slicing-by-16)
+static SLICING_TABLES: [[u32; 256]; 16] = slicing_tables();
+
+const fn slicing_tables() -> [[u32; 256]; 16] {
+    let mut tables = [[0u32; 256]; 16];
+    tables[0] = TABLE;
+    let mut n = 1;
+    while n < 16 {
+        let mut i = 0;
+        while i < 256 {
+            let previous = tables[n - 1][i];
+            tables[n][i] = (previous >> 8) ^ TABLE[(previous & 0xff) as usize];
+            i += 1;
+        }
+        n += 1;
+    }
+    tables
+}
+
 fn crc32_adjust(crc: u32, input: u8) -> u32 {
-    return ((crc >> 8) & 0x00ffffff) ^ TABLE[((crc ^ input as u32) & 0xff) as usize];
+    ((crc >> 8) & 0x00ffffff) ^ TABLE[((crc ^ input as u32) & 0xff) as usize]
+}
+
+/// Feeds `input` into the crc32 state using the fastest implementation the cpu supports
+fn update_state(state: u32, input: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if input.len() >= pclmul::MIN_LENGTH && is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("sse4.1") {
+            // Safe as the cpu supports the instructions used
+            return unsafe { pclmul::update(state, input) };
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("crc") {
+            // Safe as the cpu supports the instructions used
+            return unsafe { arm::update(state, input) };
+        }
+    }
+    update_slicing(state, input)
+}
+
+/// Portable implementation, 16 bytes at a time with `SLICING_TABLES`, then byte by byte
+fn update_slicing(state: u32, input: &[u8]) -> u32 {
+    let tables = &SLICING_TABLES;
+    let mut state = state;
+    let mut chunks = input.chunks_exact(16);
+    for chunk in &mut chunks {
+        let first = state ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        state = tables[15][(first & 0xff) as usize]
+            ^ tables[14][((first >> 8) & 0xff) as usize]
+            ^ tables[13][((first >> 16) & 0xff) as usize]
+            ^ tables[12][(first >> 24) as usize];
+        for (i, byte) in chunk[4..].iter().enumerate() {
+            state ^= tables[11 - i][*byte as usize];
+        }
+    }
+    for byte in chunks.remainder() {
+        state = crc32_adjust(state, *byte);
+    }
+    state
+}
+
+/// Implementation using the carry-less multiplication of PCLMULQDQ, following Intel's "Fast CRC
+/// Computation for Generic Polynomials Using PCLMULQDQ Instruction", folding 64 bytes at a time.
+///
+/// The crc32 instruction of SSE4.2 isn't used as it calculates the CRC-32C checksum, which uses
+/// a different polynomial than the one in UPS patches.
+#[cfg(target_arch = "x86_64")]
+mod pclmul {
+    use std::arch::x86_64::*;
+
+    /// Below this length the setup costs more than what it saves
+    pub(super) const MIN_LENGTH: usize = 128;
+
+    // x^(4*128+32) mod P, x^(4*128-32) mod P, x^(128+32) mod P, x^(128-32) mod P, x^64 mod P,
+    // all bit reflected and shifted one bit, P itself and floor(x^64 / P)
+    const K1: i64 = 0x154442bd4;
+    const K2: i64 = 0x1c6e41596;
+    const K3: i64 = 0x1751997d0;
+    const K4: i64 = 0x0ccaa009e;
+    const K5: i64 = 0x163cd6124;
+    const P_X: i64 = 0x1db710641;
+    const U_PRIME: i64 = 0x1f7011641;
+
+    /// # Safety
+    /// The cpu must support the pclmulqdq and sse4.1 instructions, and `input` must be at
+    /// least `MIN_LENGTH` bytes long
+    #[target_feature(enable = "pclmulqdq", enable = "sse2", enable = "sse4.1")]
+    pub(super) unsafe fn update(state: u32, input: &[u8]) -> u32 {
+        let mut input = input;
+        let mut x3 = next_block(&mut input);
+        let mut x2 = next_block(&mut input);
+        let mut x1 = next_block(&mut input);
+        let mut x0 = next_block(&mut input);
+        x3 = _mm_xor_si128(x3, _mm_cvtsi32_si128(state as i32));
+
+        // Fold 4 blocks of 16 bytes at a time
+        let k1k2 = _mm_set_epi64x(K2, K1);
+        while input.len() >= 64 {
+            x3 = fold(x3, next_block(&mut input), k1k2);
+            x2 = fold(x2, next_block(&mut input), k1k2);
+            x1 = fold(x1, next_block(&mut input), k1k2);
+            x0 = fold(x0, next_block(&mut input), k1k2);
+        }
+
+        // Fold the 4 blocks into one, and then the rest of the blocks into it
+        let k3k4 = _mm_set_epi64x(K4, K3);
+        let mut x = fold(x3, x2, k3k4);
+        x = fold(x, x1, k3k4);
+        x = fold(x, x0, k3k4);
+        while input.len() >= 16 {
+            x = fold(x, next_block(&mut input), k3k4);
+        }
+
+        // Reduce the 128 bits to 64 bits
+        let low_32 = _mm_set_epi32(0, 0, 0, !0);
+        let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        let x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction to 32 bits
+        let pu = _mm_set_epi64x(U_PRIME, P_X);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+        let state = _mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32;
+
+        super::update_slicing(state, input)
+    }
+
+    #[target_feature(enable = "pclmulqdq", enable = "sse2")]
+    unsafe fn fold(a: __m128i, b: __m128i, keys: __m128i) -> __m128i {
+        let low = _mm_clmulepi64_si128(a, keys, 0x00);
+        let high = _mm_clmulepi64_si128(a, keys, 0x11);
+        _mm_xor_si128(_mm_xor_si128(b, low), high)
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn next_block(input: &mut &[u8]) -> __m128i {
+        let block = _mm_loadu_si128(input.as_ptr() as *const __m128i);
+        *input = &input[16..];
+        block
+    }
+}
+
+/// Implementation using the crc32 instructions of ARMv8, which use the same polynomial as UPS
+/// patches
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use std::arch::aarch64::*;
+
+    /// # Safety
+    /// The cpu must support the crc instructions
+    #[target_feature(enable = "crc")]
+    pub(super) unsafe fn update(state: u32, input: &[u8]) -> u32 {
+        let mut state = state;
+        let mut chunks = input.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            state = __crc32d(state, u64::from_le_bytes(word));
+        }
+        for byte in chunks.remainder() {
+            state = __crc32b(state, *byte);
+        }
+        state
+    }
+}
+
+/// Incremental crc32 calculation, for data that isn't available all at once
+///
+/// Gives the same checksums stored in UPS and BPS patches, so a file can be checked against
+/// [`UpsPatch::source_crc32`](crate::UpsPatch::source_crc32) while it is being downloaded or
+/// read from disk, without having it all in memory. It implements `Write`, so it can be the
+/// destination of [`io::copy`].
+/// # Examples
+/// ```no_run
+/// # use ups::{Crc32, UpsPatch};
+/// # use std::fs::File;
+/// # use std::io;
+/// # let patch = UpsPatch::load(&vec![]).unwrap();
+/// let mut crc32 = Crc32::new();
+/// io::copy(&mut File::open("path/to/source/file").unwrap(), &mut crc32).unwrap();
+/// if crc32.finish() != patch.source_crc32 {
+///     println!("That isn't the source file of the patch");
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Creates a checksum with no data fed into it yet
+    pub fn new() -> Crc32 {
+        Crc32 { state: 0xffffffff }
+    }
+
+    /// Feeds more data into the checksum
+    pub fn update(&mut self, input: &[u8]) {
+        self.state = update_state(self.state, input);
+    }
+
+    /// Returns the checksum of all the data fed so far, more data can still be fed afterwards
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Write for Crc32 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 pub(crate) fn calculate(input: &[u8]) -> u32 {
-    let mut crc32 = 0xffffffff;
-    for i in 0..input.len() {
-        crc32 = crc32_adjust(crc32, input[i])
+    let mut crc32 = Crc32::new();
+    crc32.update(input);
+    crc32.finish()
+}
+
+
+/// Size of the pieces the input is split into to calculate their checksums in parallel
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_SIZE: usize = 1 << 20;
+
+/// Calculates the crc32 of `input` using all the available cores, giving the same result as
+/// [`calculate`]
+#[cfg(feature = "rayon")]
+pub(crate) fn calculate_parallel(input: &[u8]) -> u32 {
+    use rayon::prelude::*;
+
+    input.par_chunks(PARALLEL_CHUNK_SIZE)
+        .map(|chunk| (calculate(chunk), chunk.len() as u64))
+        .collect::<Vec<(u32, u64)>>()
+        .into_iter()
+        .fold(calculate(&[]), |crc1, (crc2, length2)| combine(crc1, crc2, length2))
+}
+
+/// Returns the crc32 of two pieces of data one after the other, given the crc32 of each one and
+/// the length of the second one, without having to go through the data again
+///
+/// Appending `length2` bytes to the first piece is a linear operation on its crc32, which is
+/// done by repeatedly squaring the operator that appends a single zero bit, same as zlib does.
+pub(crate) fn combine(crc1: u32, crc2: u32, length2: u64) -> u32 {
+    if length2 == 0 {
+        return crc1;
+    }
+    // Operator that appends one zero bit
+    let mut odd = [0u32; 32];
+    odd[0] = 0xedb88320;
+    for (n, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (n - 1);
+    }
+    let mut even = [0u32; 32];
+    // Operators for two and four zero bits
+    gf2_matrix_square(&mut even, &odd);
+    gf2_matrix_square(&mut odd, &even);
+
+    // Each step squares the operator, appending twice as many zero bits as the previous one,
+    // starting from a whole byte
+    let mut crc1 = crc1;
+    let mut length2 = length2;
+    loop {
+        gf2_matrix_square(&mut even, &odd);
+        if length2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        length2 >>= 1;
+        if length2 == 0 {
+            break;
+        }
+        gf2_matrix_square(&mut odd, &even);
+        if length2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        length2 >>= 1;
+        if length2 == 0 {
+            break;
+        }
+    }
+    crc1 ^ crc2
+}
+
+fn gf2_matrix_times(matrix: &[u32; 32], vector: u32) -> u32 {
+    let mut sum = 0;
+    let mut vector = vector;
+    for row in matrix {
+        if vector == 0 {
+            break;
+        }
+        if vector & 1 != 0 {
+            sum ^= row;
+        }
+        vector >>= 1;
+    }
+    sum
+}
+
+fn gf2_matrix_square(square: &mut [u32; 32], matrix: &[u32; 32]) {
+    for (square_row, row) in square.iter_mut().zip(matrix) {
+        *square_row = gf2_matrix_times(matrix, *row);
     }
-    return !crc32;
 }
 
+#[cfg(test)]
+mod internal_tests {
+    use crate::crc32;
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    /// The original implementation, one byte at a time, that the others have to match
+    fn calculate_bytewise(input: &[u8]) -> u32 {
+        !input.iter().fold(0xffffffff, |state, byte| crc32::crc32_adjust(state, *byte))
+    }
+
+    fn pseudo_random_bytes(length: usize) -> Vec<u8> {
+        let mut state: u32 = 0x12345678;
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    /// Feeds data into a raw crc32 state, like `update_state`
+    type Update = fn(u32, &[u8]) -> u32;
+
+    /// Every implementation this cpu supports, along with its name
+    fn implementations() -> Vec<(&'static str, Update)> {
+        let mut implementations: Vec<(&'static str, Update)> = vec![
+            ("slicing-by-16", crc32::update_slicing),
+            ("dispatched", crc32::update_state),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("sse4.1") {
+                implementations.push(("pclmulqdq", |state, input| {
+                    if input.len() < crc32::pclmul::MIN_LENGTH {
+                        crc32::update_slicing(state, input)
+                    } else {
+                        unsafe { crc32::pclmul::update(state, input) }
+                    }
+                }));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("crc") {
+                implementations.push(("armv8 crc", |state, input| unsafe { crc32::arm::update(state, input) }));
+            }
+        }
+        implementations
+    }
+
+    #[test]
+    fn slicing_tables_start_with_table(){
+        assert_eq!(crc32::SLICING_TABLES[0], crc32::TABLE);
+    }
+
+    #[test]
+    fn all_implementations_match_bytewise(){
+        let content = pseudo_random_bytes(5000);
+        for (name, update) in implementations() {
+            // Every length up to a few times the blocks of each implementation, at every alignment
+            for start in 0..16 {
+                for length in (0..700).chain([4983 - start]) {
+                    let input = &content[start..start + length];
+                    assert_eq!(!update(0xffffffff, input), calculate_bytewise(input), "{} with {} bytes at {}", name, length, start);
+                }
+            }
+            // Continuing from a previous state
+            let (first, second) = content.split_at(1234);
+            assert_eq!(!update(update(0xffffffff, first), second), calculate_bytewise(&content), "{} split", name);
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_calculate(){
+        let content = pseudo_random_bytes(100_000);
+        let mut crc32 = crc32::Crc32::new();
+        let mut rest: &[u8] = &content;
+        let mut size = 1;
+        while !rest.is_empty() {
+            let (piece, remaining) = rest.split_at(size.min(rest.len()));
+            crc32.update(piece);
+            rest = remaining;
+            size = size * 3 % 997;
+        }
+        assert_eq!(crc32.finish(), crc32::calculate(&content));
+        assert_eq!(crc32::calculate(b"123456789"), 0xcbf43926);
+    }
+
+    /// Compares the speed of every implementation with the original one, run it with
+    /// `cargo test --release -- --ignored --nocapture crc32_benchmark`
+    #[test]
+    #[ignore]
+    fn crc32_benchmark(){
+        let content = pseudo_random_bytes(64 << 20);
+        let throughput = |update: &dyn Fn(&[u8]) -> u32| {
+            let start = Instant::now();
+            let mut result = 0;
+            for _ in 0..4 {
+                result = black_box(update(black_box(&content)));
+            }
+            let seconds = start.elapsed().as_secs_f64();
+            (4.0 * content.len() as f64 / seconds / (1 << 20) as f64, result)
+        };
+        let (bytewise, expected) = throughput(&calculate_bytewise);
+        println!("{:<14} {:>9.1} MiB/s", "bytewise", bytewise);
+        for (name, update) in implementations() {
+            let (speed, result) = throughput(&|input| !update(0xffffffff, input));
+            println!("{:<14} {:>9.1} MiB/s, {:.1}x", name, speed, speed / bytewise);
+            assert_eq!(result, expected);
+            assert!(speed > bytewise, "{} is slower than the bytewise implementation", name);
+        }
+    }
+
+    #[test]
+    fn can_combine_checksums(){
+        let content: Vec<u8> = (0..5000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        for split in [0, 1, 7, 100, 2500, 4999, 5000] {
+            let (first, second) = content.split_at(split);
+            let combined = crc32::combine(crc32::calculate(first), crc32::calculate(second), second.len() as u64);
+            assert_eq!(combined, crc32::calculate(&content));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn can_calculate_in_parallel(){
+        let content: Vec<u8> = (0..(3 << 20) + 12345u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        assert_eq!(crc32::calculate_parallel(&content), crc32::calculate(&content));
+        assert_eq!(crc32::calculate_parallel(&[]), crc32::calculate(&[]));
+    }
+}
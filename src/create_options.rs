@@ -0,0 +1,50 @@
+/// Options that change how [`UpsPatch::create_with_options`](crate::UpsPatch::create_with_options)
+/// builds the patch
+///
+/// There is no option to merge hunks across the unchanged bytes between them, as it can't be
+/// done: the XOR of an unchanged byte is 0, which is the byte that ends a hunk. The 0 that ends each
+/// hunk already stands for the first unchanged byte after it, so a single unchanged byte between
+/// two hunks only costs the 1 byte offset of the next hunk, and longer gaps never cost more than
+/// the bytes they skip. This makes the encoding of a given set of changes the smallest possible
+/// one.
+///
+/// The only bytes a patch can leave out are the ones at the end of the source file when the
+/// target file is shorter, as they get truncated when applying the patch, see
+/// [`drop_source_tail`](CreateOptions::drop_source_tail). There is no choice of the byte the
+/// shorter file gets padded with, as every UPS patcher pads it with zeros.
+/// # Examples
+/// ```
+/// # use ups::{CreateOptions, UpsPatch};
+/// let patch = UpsPatch::create_with_options(b"source", b"target", &CreateOptions::fastest());
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CreateOptions {
+    /// Compares the files using all the cores, same as `UpsPatch::create_parallel` does. It only
+    /// has an effect when the crate is built with the `rayon` feature
+    pub parallel: bool,
+    /// Leaves out of the patch the bytes of the source file past the end of the target file,
+    /// treating them as don't-care. Applying the patch works the same, but reverting it can't
+    /// restore them anymore, so it fails with
+    /// [`TargetMismatch`](crate::ApplyError::TargetMismatch) unless they were all zeros.
+    ///
+    /// The same goes for the patch given by [`invert`](crate::UpsPatch::invert), and for
+    /// [`compose`](crate::UpsPatch::compose) when the patch is the first one and the second one
+    /// grows the file back, as both need the dropped bytes. The patch itself doesn't tell whether
+    /// its tail was dropped, so they can't detect it
+    pub drop_source_tail: bool,
+}
+
+impl CreateOptions {
+    /// The options that create patches the fastest
+    pub fn fastest() -> CreateOptions {
+        CreateOptions { parallel: true, drop_source_tail: false }
+    }
+
+    /// The options that create the smallest patches, which only differ from the default ones in
+    /// [`drop_source_tail`](CreateOptions::drop_source_tail). When the target file is shorter than
+    /// the source file, those patches can't be reverted, inverted or composed with a patch that
+    /// grows the file back
+    pub fn smallest() -> CreateOptions {
+        CreateOptions { parallel: false, drop_source_tail: true }
+    }
+}
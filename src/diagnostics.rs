@@ -0,0 +1,168 @@
+use std::convert::TryFrom;
+
+use crate::{crc32, Direction, FileVerdict, HeaderFixup, UpsPatch};
+
+/// Size of the blocks of SMD Mega Drive/Genesis dumps, which interleave the bytes of each one
+const SMD_BLOCK_SIZE: usize = 16 * 1024;
+/// Size of the header at the start of SMD dumps
+const SMD_HEADER_SIZE: usize = 512;
+/// Byte that fills the unused space of trimmed and overdumped ROMs
+const FILL_BYTE: u8 = 0xff;
+
+/// A well known way a ROM dump can differ from the one a patch was made for, tried by
+/// [`UpsPatch::diagnose_source`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SourceTransform {
+    /// Swaps each pair of bytes, converting between the `.v64` and `.z64` N64 formats
+    ByteSwap,
+    /// Reverses each group of 4 bytes, converting between the `.n64` and `.z64` N64 formats
+    WordSwap,
+    /// Removes the header and undoes the interleaving of SMD Mega Drive/Genesis dumps, turning
+    /// them into BIN dumps
+    Deinterleave,
+    /// Adds back the `0xFF` bytes removed from the end of a trimmed ROM, as is often done with
+    /// NDS ROMs
+    PadWithFf,
+    /// Removes the `0xFF` bytes past the end of the ROM in an overdump
+    TrimOverdump,
+    /// Removes or adds a ROM header, same as [`UpsPatch::apply_with_fixup`] does
+    Header(HeaderFixup),
+}
+
+/// Why a file is or isn't the source file of a patch, returned by
+/// [`UpsPatch::diagnose_source`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SourceDiagnosis {
+    /// No transform was tried because the file is already the source or the target of the
+    /// patch, or none of them turned it into the source, in which case this tells how it differs
+    AsIs(FileVerdict),
+    /// The file turns into the source file of the patch after `transform`
+    Repairable {
+        transform: SourceTransform,
+        /// The file after the transform, ready to be given to [`UpsPatch::apply`]
+        content: Vec<u8>,
+    },
+}
+
+impl SourceTransform {
+    /// Applies the transform to `content`, returning `None` if it can't give a file of `size`
+    /// bytes or the content doesn't look like what it converts from.
+    ///
+    /// Padding is checked against `crc32` before building the padded file, as the size comes
+    /// from the patch and can be way more than fits in memory.
+    // Kept as `%` like the SMD block check, so the crate builds before Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    fn convert(&self, content: &[u8], size: u64, crc32: u32) -> Option<Vec<u8>> {
+        let len = content.len() as u64;
+        match self {
+            SourceTransform::ByteSwap if len == size && len % 2 == 0 => {
+                Some(content.chunks_exact(2).flat_map(|pair| [pair[1], pair[0]]).collect())
+            }
+            SourceTransform::WordSwap if len == size && len % 4 == 0 => {
+                Some(content.chunks_exact(4).flat_map(|word| [word[3], word[2], word[1], word[0]]).collect())
+            }
+            SourceTransform::Deinterleave
+                if len == size + SMD_HEADER_SIZE as u64 && (len as usize) % SMD_BLOCK_SIZE == SMD_HEADER_SIZE => {
+                // Each block has the bytes at odd positions in its first half and the ones at
+                // even positions in its second half
+                let half = SMD_BLOCK_SIZE / 2;
+                let mut output = vec![0u8; size as usize];
+                for (block, output_block) in content[SMD_HEADER_SIZE..].chunks_exact(SMD_BLOCK_SIZE)
+                    .zip(output.chunks_exact_mut(SMD_BLOCK_SIZE)) {
+                    for i in 0..half {
+                        output_block[2 * i + 1] = block[i];
+                        output_block[2 * i] = block[half + i];
+                    }
+                }
+                Some(output)
+            }
+            SourceTransform::PadWithFf if len < size => {
+                let padding = size - len;
+                if crc32::combine(crc32::calculate(content), fill_crc32(padding), padding) != crc32 {
+                    return None;
+                }
+                let size = usize::try_from(size).ok()?;
+                let mut output: Vec<u8> = vec![];
+                output.try_reserve_exact(size).ok()?;
+                output.extend_from_slice(content);
+                output.resize(size, FILL_BYTE);
+                Some(output)
+            }
+            SourceTransform::TrimOverdump if len > size && content[size as usize..].iter().all(|byte| *byte == FILL_BYTE) => {
+                Some(content[..size as usize].to_vec())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the crc32 of `length` bytes of `FILL_BYTE`, combining the crc32 of runs that double in
+/// length each time, so that it takes the same time for any length
+fn fill_crc32(length: u64) -> u32 {
+    let mut crc = crc32::calculate(&[]);
+    let mut run_crc = crc32::calculate(&[FILL_BYTE]);
+    let mut run_length: u64 = 1;
+    let mut remaining = length;
+    while remaining != 0 {
+        if remaining & 1 != 0 {
+            crc = crc32::combine(crc, run_crc, run_length);
+        }
+        remaining >>= 1;
+        if remaining != 0 {
+            run_crc = crc32::combine(run_crc, run_crc, run_length);
+            run_length <<= 1;
+        }
+    }
+    crc
+}
+
+impl UpsPatch {
+    /// Finds out why `content` isn't the source file of the patch, trying the well known ways
+    /// ROM dumps differ from each other: N64 byte orders, SMD interleaving, trimmed ROMs,
+    /// overdumps and ROM headers.
+    ///
+    /// Each transform is only tried when it gives a file of the size of the source file, and
+    /// the first one that gives the source file is returned along with the converted file.
+    /// # Arguments
+    /// * `content` - The content of the file that should be the source file
+    /// # Examples
+    /// ```no_run
+    /// # use ups::{SourceDiagnosis, UpsPatch};
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// # let rom_content: Vec<u8> = vec![];
+    /// match patch.diagnose_source(&rom_content) {
+    ///     SourceDiagnosis::Repairable { transform, content } => {
+    ///         println!("The ROM needs to be converted: {:?}", transform);
+    ///         let patched_rom_content = patch.apply(&content).unwrap();
+    ///     }
+    ///     SourceDiagnosis::AsIs(verdict) => println!("{:?}", verdict),
+    /// }
+    /// ```
+    pub fn diagnose_source(&self, content: &[u8]) -> SourceDiagnosis {
+        let verdict = self.verdict(content.len() as u64, crc32::calculate(content), Direction::Forward);
+        if let FileVerdict::Source | FileVerdict::Target = verdict {
+            return SourceDiagnosis::AsIs(verdict);
+        }
+        let transforms = [
+            SourceTransform::ByteSwap,
+            SourceTransform::WordSwap,
+            SourceTransform::Deinterleave,
+            SourceTransform::PadWithFf,
+            SourceTransform::TrimOverdump,
+        ];
+        for transform in &transforms {
+            if let Some(converted) = transform.convert(content, self.source_file_size, self.source_crc32) {
+                if self.file_is_source(&converted) {
+                    return SourceDiagnosis::Repairable { transform: *transform, content: converted };
+                }
+            }
+        }
+        match self.find_header_fixup(content) {
+            Some((fixup, fixed_content)) => SourceDiagnosis::Repairable {
+                transform: SourceTransform::Header(fixup),
+                content: fixed_content,
+            },
+            None => SourceDiagnosis::AsIs(verdict),
+        }
+    }
+}
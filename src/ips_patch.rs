@@ -0,0 +1,262 @@
+use std::cmp::min;
+
+use crate::{
+    UpsError,
+    LoadError::*,
+    CreateError::*,
+};
+
+/// A single record of an IPS patch
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IpsRecord {
+    /// Bytes that get written as they are into the file, starting at `offset`
+    Data {
+        offset: u32,
+        data: Vec<u8>,
+    },
+    /// A run of `length` bytes with the same `value` that gets written into the file, starting
+    /// at `offset`
+    Rle {
+        offset: u32,
+        length: u16,
+        value: u8,
+    },
+}
+
+///Represents an ips patch
+///
+/// IPS patches just overwrite parts of the file with new bytes, so unlike UPS patches they don't
+/// contain any checksum and can be applied to any file.
+#[derive(Debug, Eq, PartialEq)]
+pub struct IpsPatch {
+    /// The records that have to be written into the original file to get the final file, in the
+    /// order they are applied
+    pub records: Vec<IpsRecord>,
+    /// The size the final file gets truncated to, from the truncation extension of the format.
+    /// `None` if the patch doesn't use it
+    pub truncate_size: Option<u32>,
+}
+
+impl IpsPatch {
+    pub(crate) const CANON_HEADER: [u8; 5] = *b"PATCH";
+    const FOOTER: [u8; 3] = *b"EOF";
+    /// Offsets are stored with 3 bytes, so this one can't be used by a record as it would be read
+    /// as the end of the patch
+    const EOF_OFFSET: usize = 0x454F46;
+    const MAX_OFFSET: usize = 0xFFFFFF;
+    const MAX_RECORD_SIZE: usize = 0xFFFF;
+    /// A run of equal bytes at the start or end of a changed area takes less space as an RLE
+    /// record once it is this long
+    const MIN_RLE_AT_EDGE: usize = 9;
+    /// A run of equal bytes in the middle of a changed area takes less space as an RLE record
+    /// once it is this long, as the data following it needs a record of its own
+    const MIN_RLE_IN_MIDDLE: usize = 14;
+
+    /// Creates a Patch from the given source and target files
+    ///
+    /// Returns an error if the target file is too big to be addressed by an IPS patch
+    /// # Arguments
+    /// * `source_content` - The contents of the source/original file
+    /// * `target_content` - The contents of the target/final file
+    ///
+    pub fn create(source_content: &[u8], target_content: &[u8]) -> Result<IpsPatch, UpsError> {
+        let truncate_size = if target_content.len() < source_content.len() {
+            Some(target_content.len())
+        } else {
+            None
+        };
+        // The truncation size is stored with 3 bytes too, so it can't reach the end of the addressable range
+        let max_size = if truncate_size.is_some() { IpsPatch::MAX_OFFSET } else { IpsPatch::MAX_OFFSET + 1 };
+        if target_content.len() > max_size {
+            return Err(UpsError::Create(FileTooBig {
+                size: target_content.len() as u64,
+                max_size: max_size as u64,
+            }));
+        }
+
+        let mut records: Vec<IpsRecord> = vec![];
+        let is_changed = |i: usize| i >= source_content.len() || source_content[i] != target_content[i];
+        let mut i = 0;
+        while i < target_content.len() {
+            if is_changed(i) {
+                let change_start = i;
+                while i < target_content.len() && is_changed(i) {
+                    i += 1;
+                }
+                IpsPatch::push_changed_area(&mut records, target_content, change_start, i);
+            }
+            i += 1;
+        }
+
+        Ok(IpsPatch {
+            records,
+            truncate_size: truncate_size.map(|size| size as u32),
+        })
+    }
+
+    /// Adds the records needed to write `target_content[start..end]`, using RLE records for the
+    /// runs of equal bytes long enough to make it worth it
+    fn push_changed_area(records: &mut Vec<IpsRecord>, target_content: &[u8], start: usize, end: usize) {
+        let mut data_start = start;
+        let mut i = start;
+        while i < end {
+            let value = target_content[i];
+            let mut run_end = i;
+            while run_end < end && target_content[run_end] == value {
+                run_end += 1;
+            }
+            let min_rle = if i == data_start || run_end == end {
+                IpsPatch::MIN_RLE_AT_EDGE
+            } else {
+                IpsPatch::MIN_RLE_IN_MIDDLE
+            };
+            if run_end - i >= min_rle {
+                IpsPatch::push_data(records, target_content, data_start, i);
+                IpsPatch::push_rle(records, target_content, i, run_end);
+                data_start = run_end;
+            }
+            i = run_end;
+        }
+        IpsPatch::push_data(records, target_content, data_start, end);
+    }
+
+    fn push_data(records: &mut Vec<IpsRecord>, target_content: &[u8], start: usize, end: usize) {
+        let mut start = start;
+        while start < end {
+            if start == IpsPatch::EOF_OFFSET {
+                // Writing the previous byte again is harmless, as it is taken from the target too
+                start -= 1;
+            }
+            let length = min(end - start, IpsPatch::MAX_RECORD_SIZE);
+            records.push(IpsRecord::Data {
+                offset: start as u32,
+                data: target_content[start..start + length].to_vec(),
+            });
+            start += length;
+        }
+    }
+
+    fn push_rle(records: &mut Vec<IpsRecord>, target_content: &[u8], start: usize, end: usize) {
+        let mut start = start;
+        while start < end {
+            if start == IpsPatch::EOF_OFFSET {
+                IpsPatch::push_data(records, target_content, start, start + 1);
+                start += 1;
+                continue;
+            }
+            let length = min(end - start, IpsPatch::MAX_RECORD_SIZE);
+            records.push(IpsRecord::Rle {
+                offset: start as u32,
+                length: length as u16,
+                value: target_content[start],
+            });
+            start += length;
+        }
+    }
+
+    /// Loads an already existing patch, if the given file contents don't contain a valid IPS patch returns a UpsError
+    /// # Arguments
+    /// * `content` - The content of the patch file to load
+    pub fn load(content: &[u8]) -> Result<IpsPatch, UpsError> {
+        if !content.starts_with(&IpsPatch::CANON_HEADER) {
+            return Err(UpsError::Load(IsNotIpsFile));
+        }
+        let mut records: Vec<IpsRecord> = vec![];
+        let mut i = IpsPatch::CANON_HEADER.len();
+        loop {
+            let record_start = i;
+            let take = |i: usize, length: usize| {
+                content.get(i..i + length).ok_or(UpsError::Load(Truncated { offset: record_start as u64 }))
+            };
+            let offset = take(i, 3)?;
+            i += 3;
+            if offset == IpsPatch::FOOTER {
+                break;
+            }
+            let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]);
+            let size = take(i, 2)?;
+            let size = u16::from_be_bytes([size[0], size[1]]);
+            i += 2;
+            if size == 0 {
+                let rle = take(i, 3)?;
+                i += 3;
+                records.push(IpsRecord::Rle {
+                    offset,
+                    length: u16::from_be_bytes([rle[0], rle[1]]),
+                    value: rle[2],
+                });
+            } else {
+                let data = take(i, size as usize)?;
+                i += size as usize;
+                records.push(IpsRecord::Data {
+                    offset,
+                    data: data.to_vec(),
+                });
+            }
+        }
+
+        let truncate_size = match content.len() - i {
+            0 => None,
+            3 => Some(u32::from_be_bytes([0, content[i], content[i + 1], content[i + 2]])),
+            _ => return Err(UpsError::Load(UnexpectedData { offset: i as u64 })),
+        };
+        Ok(IpsPatch {
+            records,
+            truncate_size,
+        })
+    }
+
+    /// Applies the patch to a given source file contents.
+    ///
+    /// IPS patches have no way to verify the source file, so this never fails.
+    pub fn apply(&self, source: &[u8]) -> Vec<u8> {
+        let mut output = source.to_vec();
+        for record in &self.records {
+            let (offset, length) = match record {
+                IpsRecord::Data { offset, data } => (*offset as usize, data.len()),
+                IpsRecord::Rle { offset, length, .. } => (*offset as usize, *length as usize),
+            };
+            if output.len() < offset + length {
+                output.resize(offset + length, 0);
+            }
+            match record {
+                IpsRecord::Data { data, .. } => output[offset..offset + length].copy_from_slice(data),
+                IpsRecord::Rle { value, .. } => {
+                    for byte in &mut output[offset..offset + length] {
+                        *byte = *value;
+                    }
+                }
+            }
+        }
+        if let Some(truncate_size) = self.truncate_size {
+            output.truncate(truncate_size as usize);
+        }
+        output
+    }
+
+    /// Returns a vector with the contents of the patch.ips file
+    pub fn get_patch_file_contents(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+        output.extend(IpsPatch::CANON_HEADER);
+        for record in &self.records {
+            match record {
+                IpsRecord::Data { offset, data } => {
+                    output.extend(&offset.to_be_bytes()[1..]);
+                    output.extend((data.len() as u16).to_be_bytes());
+                    output.extend(data);
+                }
+                IpsRecord::Rle { offset, length, value } => {
+                    output.extend(&offset.to_be_bytes()[1..]);
+                    output.extend([0, 0]);
+                    output.extend(length.to_be_bytes());
+                    output.push(*value);
+                }
+            }
+        }
+        output.extend(IpsPatch::FOOTER);
+        if let Some(truncate_size) = self.truncate_size {
+            output.extend(&truncate_size.to_be_bytes()[1..]);
+        }
+        output
+    }
+}
@@ -28,6 +28,22 @@
 //! # Ok(())
 //! # }
 //! ```
+//! ### Revert UPS patch:
+//! ```no_run
+//! use ups::UpsPatch;
+//! # use std::error::Error;
+//! # fn main() -> Result<(), Box<dyn Error>>{
+//! # let patch_file_content: Vec<u8> = vec![];
+//! # let target_file_content: Vec<u8> = vec![];
+//! //UPS patches work both ways, apply detects when it is given the target file
+//! let patch = UpsPatch::load(&patch_file_content)?;
+//! let source_file_content = patch.apply(&target_file_content)?;
+//! //Or revert can be used to only accept the target file
+//! let source_file_content = patch.revert(&target_file_content)?;
+//!
+//! # Ok(())
+//! # }
+//! ```
 //! ### Create UPS Patch
 //! ```no_run
 //! use ups::UpsPatch;
@@ -56,8 +72,30 @@
 //! ```
 
 
-pub use crate::ups_patch::{UpsPatch};
+pub use crate::ups_patch::{UpsPatch, Direction, FileVerdict};
+pub use crate::ups_reader::{UpsReader};
+pub use crate::ups_writer::{UpsWriter};
+pub use crate::ups_summary::{UpsSummary};
+pub use crate::apply_options::{ApplyOptions};
+pub use crate::create_options::{CreateOptions};
+pub use crate::rom_header::{RomHeader, HeaderFixup};
+pub use crate::diagnostics::{SourceTransform, SourceDiagnosis};
+pub use crate::crc32::{Crc32};
+pub use crate::ips_patch::{IpsPatch, IpsRecord};
+pub use crate::bps_patch::{BpsPatch, BpsAction};
+pub use crate::patch::{Patch, PatchFormat, detect, load_any};
 pub use crate::ups_error::{UpsError, ApplyError, CreateError, LoadError};
 mod ups_patch;
+mod ups_reader;
+mod ups_writer;
+mod ups_summary;
+mod apply_options;
+mod create_options;
+mod rom_header;
+mod diagnostics;
+mod ips_patch;
+mod bps_patch;
+mod patch;
 mod crc32;
+mod varint;
 mod ups_error;
\ No newline at end of file
@@ -0,0 +1,152 @@
+use crate::{
+    BpsPatch,
+    IpsPatch,
+    UpsError,
+    UpsPatch,
+    LoadError::*,
+};
+
+/// The patch file formats that can be recognised by [`detect`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PatchFormat {
+    Ups,
+    Ips,
+    Bps,
+    /// PlayStation Patch Format, recognised but not supported
+    Ppf,
+    /// xdelta/VCDIFF, recognised but not supported
+    Vcdiff,
+}
+
+/// Operations shared by all the supported patch formats, so that patches can be handled without
+/// knowing their format, like the ones returned by [`load_any`]
+pub trait Patch {
+    /// The format of the patch
+    fn format(&self) -> PatchFormat;
+
+    /// Applies the patch to the given file contents, verifying the source and target files when
+    /// the format has the checksums to do so
+    fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError>;
+
+    /// Checks if the given file contents matches the source file for the patch.
+    /// Formats without checksums accept any file
+    fn file_is_source(&self, content: &[u8]) -> bool;
+
+    /// Checks if the given file contents matches the target file for the patch.
+    /// Formats without checksums accept any file
+    fn file_is_target(&self, content: &[u8]) -> bool;
+
+    /// Returns a vector with the contents of the patch file
+    fn get_patch_file_contents(&self) -> Vec<u8>;
+}
+
+impl Patch for UpsPatch {
+    fn format(&self) -> PatchFormat {
+        PatchFormat::Ups
+    }
+
+    fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        UpsPatch::apply(self, source)
+    }
+
+    fn file_is_source(&self, content: &[u8]) -> bool {
+        UpsPatch::file_is_source(self, content)
+    }
+
+    fn file_is_target(&self, content: &[u8]) -> bool {
+        UpsPatch::file_is_target(self, content)
+    }
+
+    fn get_patch_file_contents(&self) -> Vec<u8> {
+        UpsPatch::get_patch_file_contents(self)
+    }
+}
+
+impl Patch for IpsPatch {
+    fn format(&self) -> PatchFormat {
+        PatchFormat::Ips
+    }
+
+    fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        Ok(IpsPatch::apply(self, source))
+    }
+
+    fn file_is_source(&self, _content: &[u8]) -> bool {
+        true
+    }
+
+    fn file_is_target(&self, _content: &[u8]) -> bool {
+        true
+    }
+
+    fn get_patch_file_contents(&self) -> Vec<u8> {
+        IpsPatch::get_patch_file_contents(self)
+    }
+}
+
+impl Patch for BpsPatch {
+    fn format(&self) -> PatchFormat {
+        PatchFormat::Bps
+    }
+
+    fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        BpsPatch::apply(self, source)
+    }
+
+    fn file_is_source(&self, content: &[u8]) -> bool {
+        BpsPatch::file_is_source(self, content)
+    }
+
+    fn file_is_target(&self, content: &[u8]) -> bool {
+        BpsPatch::file_is_target(self, content)
+    }
+
+    fn get_patch_file_contents(&self) -> Vec<u8> {
+        BpsPatch::get_patch_file_contents(self)
+    }
+}
+
+const PPF_HEADER: [u8; 3] = *b"PPF";
+const VCDIFF_HEADER: [u8; 3] = [0xD6, 0xC3, 0xC4];
+
+/// Recognises the format of a patch file from the magic bytes at its beginning, returns `None`
+/// if it isn't any of the known formats
+/// # Arguments
+/// * `content` - The content of the patch file, only the first few bytes are needed
+pub fn detect(content: &[u8]) -> Option<PatchFormat> {
+    if content.starts_with(&UpsPatch::CANON_HEADER) {
+        Some(PatchFormat::Ups)
+    } else if content.starts_with(&IpsPatch::CANON_HEADER) {
+        Some(PatchFormat::Ips)
+    } else if content.starts_with(&BpsPatch::CANON_HEADER) {
+        Some(PatchFormat::Bps)
+    } else if content.starts_with(&PPF_HEADER) {
+        Some(PatchFormat::Ppf)
+    } else if content.starts_with(&VCDIFF_HEADER) {
+        Some(PatchFormat::Vcdiff)
+    } else {
+        None
+    }
+}
+
+/// Loads a patch of any of the supported formats, detecting which one it is
+/// # Arguments
+/// * `content` - The content of the patch file to load
+/// # Examples
+/// ```no_run
+/// # use ups::load_any;
+/// # let patch_file_content: Vec<u8> = vec![];
+/// # let source_file_content: Vec<u8> = vec![];
+/// let patch = load_any(&patch_file_content).unwrap();
+/// println!("Applying a {:?} patch", patch.format());
+/// let target_file_content = patch.apply(&source_file_content).unwrap();
+/// ```
+pub fn load_any(content: &[u8]) -> Result<Box<dyn Patch>, UpsError> {
+    match detect(content) {
+        Some(PatchFormat::Ups) => Ok(Box::new(UpsPatch::load(content)?)),
+        Some(PatchFormat::Ips) => Ok(Box::new(IpsPatch::load(content)?)),
+        Some(PatchFormat::Bps) => Ok(Box::new(BpsPatch::load(content)?)),
+        Some(PatchFormat::Ppf) | Some(PatchFormat::Vcdiff) => Err(UpsError::Load(UnsupportedFormat)),
+        None => Err(UpsError::Load(IsNotPatchFile)),
+    }
+}
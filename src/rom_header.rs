@@ -0,0 +1,103 @@
+use crate::UpsPatch;
+
+/// Size of the header SNES copiers add at the start of the ROMs they dump
+const SNES_COPIER_HEADER_SIZE: usize = 512;
+/// Size of the iNES header of NES ROMs and the fwNES header of Famicom Disk System images
+const NES_HEADER_SIZE: usize = 16;
+/// Size of each side of a Famicom Disk System disk
+const FDS_SIDE_SIZE: usize = 65500;
+const INES_MAGIC: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+const FDS_MAGIC: [u8; 4] = [0x46, 0x44, 0x53, 0x1a];
+
+/// Headers that ROM dumps often have or lack, making them differ from the file a patch was made for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RomHeader {
+    /// The 512 byte header added by SNES copiers, found in `.smc` files
+    SnesCopier,
+    /// The 16 byte fwNES header of Famicom Disk System images
+    Fds,
+    /// The 16 byte iNES header of NES ROMs
+    Ines,
+}
+
+/// A change made to the given file so that a patch could be applied to it, returned by
+/// [`UpsPatch::apply_with_fixup`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HeaderFixup {
+    /// The header was removed from the file before applying the patch, and put back at the
+    /// start of the result
+    Stripped(RomHeader),
+    /// The patch expects a file with the header, so one was added to the file before applying
+    /// the patch, and removed from the result
+    Added(RomHeader),
+}
+
+impl RomHeader {
+    /// The size of the header in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            RomHeader::SnesCopier => SNES_COPIER_HEADER_SIZE,
+            RomHeader::Fds | RomHeader::Ines => NES_HEADER_SIZE,
+        }
+    }
+}
+
+impl HeaderFixup {
+    /// Returns every way `content` could be fixed into a file of `size` bytes, along with the
+    /// fixed content, in the order they are tried.
+    ///
+    /// Headers are only stripped when they are recognised, by their magic number for iNES and
+    /// fwNES, or by the size of the file for SNES copiers, as they make it 512 bytes more than a
+    /// multiple of 1 KiB. SNES copier headers are added both empty and with the size of the ROM
+    /// in it, as copiers fill it either way, and fwNES headers with the number of disk sides.
+    /// iNES headers depend on the hardware of each cartridge, so they are never added.
+    // `usize::is_multiple_of` needs a newer compiler than the rest of the crate
+    #[allow(clippy::manual_is_multiple_of)]
+    pub(crate) fn candidates(content: &[u8], size: u64) -> Vec<(HeaderFixup, Vec<u8>)> {
+        let mut candidates: Vec<(HeaderFixup, Vec<u8>)> = vec![];
+        let len = content.len();
+        // Only the fixups that give the right size are worth copying the content for
+        if len as u64 == size + SNES_COPIER_HEADER_SIZE as u64 && len % 1024 == SNES_COPIER_HEADER_SIZE {
+            candidates.push((HeaderFixup::Stripped(RomHeader::SnesCopier), content[SNES_COPIER_HEADER_SIZE..].to_vec()));
+        }
+        let strips_nes_header = len as u64 == size + NES_HEADER_SIZE as u64;
+        if strips_nes_header && content.starts_with(&INES_MAGIC) {
+            candidates.push((HeaderFixup::Stripped(RomHeader::Ines), content[NES_HEADER_SIZE..].to_vec()));
+        }
+        if strips_nes_header && content.starts_with(&FDS_MAGIC) {
+            candidates.push((HeaderFixup::Stripped(RomHeader::Fds), content[NES_HEADER_SIZE..].to_vec()));
+        }
+        if len as u64 + SNES_COPIER_HEADER_SIZE as u64 == size && len % 1024 == 0 {
+            let mut header = [0u8; SNES_COPIER_HEADER_SIZE];
+            candidates.push((HeaderFixup::Added(RomHeader::SnesCopier), [&header[..], content].concat()));
+            // The size is stored in units of 8 KiB
+            let blocks = (len / 8192) as u16;
+            header[0..2].copy_from_slice(&blocks.to_le_bytes());
+            candidates.push((HeaderFixup::Added(RomHeader::SnesCopier), [&header[..], content].concat()));
+        }
+        if len as u64 + NES_HEADER_SIZE as u64 == size && len > 0 && len % FDS_SIDE_SIZE == 0 {
+            let mut header = [0u8; NES_HEADER_SIZE];
+            header[0..4].copy_from_slice(&FDS_MAGIC);
+            header[4] = (len / FDS_SIDE_SIZE) as u8;
+            candidates.push((HeaderFixup::Added(RomHeader::Fds), [&header[..], content].concat()));
+        }
+        candidates
+    }
+
+    /// Undoes the fixup on the result of applying the patch to the fixed content, `original`
+    /// being the content before it was fixed
+    pub(crate) fn restore(&self, original: &[u8], output: Vec<u8>) -> Vec<u8> {
+        match self {
+            HeaderFixup::Stripped(header) => [&original[..header.size()], &output[..]].concat(),
+            HeaderFixup::Added(header) => output.get(header.size()..).unwrap_or(&[]).to_vec(),
+        }
+    }
+}
+
+impl UpsPatch {
+    /// Finds a header fixup that turns `content` into the source file of the patch
+    pub(crate) fn find_header_fixup(&self, content: &[u8]) -> Option<(HeaderFixup, Vec<u8>)> {
+        HeaderFixup::candidates(content, self.source_file_size).into_iter()
+            .find(|(_, fixed_content)| self.file_is_source(fixed_content))
+    }
+}
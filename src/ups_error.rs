@@ -1,68 +1,277 @@
-use std::{fmt, error};
+use std::{fmt, error, io};
 use std::fmt::Formatter;
 
 /// Error type returned when something goes wrong
 ///
 /// This is what is actually returned from all the functions, and its the job of hte program
 /// that implements this library to deal with the error and whatever it contains
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug)]
 pub enum UpsError{
     Load(LoadError),
     Apply(ApplyError),
     Create(CreateError),
+    /// Reading or writing one of the files failed
+    Io(io::Error),
 }
 /// Errors that happen when loading an already made patch
-#[derive(Debug, Eq, PartialEq)]
+///
+/// Offsets are positions in the patch file, counting from its first byte.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum LoadError{
     /// The given UPS file isn't actually a UPS patch file
     IsNotUpsFile,
-    /// The given UPS file is a ups file but seems to be corrupted.
-    IsCorrupted,
+    /// The given patch file has the right format but its crc32 doesn't match its contents
+    IsCorrupted {
+        /// The crc32 stored at the end of the patch
+        expected: u32,
+        /// The crc32 calculated from the contents of the patch
+        actual: u32,
+    },
+    /// The patch ends in the middle of the data that starts at `offset`
+    Truncated {
+        offset: u64,
+    },
+    /// The patch ends before the end of the number stored at `offset`
+    UnterminatedVarint {
+        offset: u64,
+    },
+    /// The number stored at `offset`, or the position it points to, doesn't fit in 64 bits
+    VarintOverflow {
+        offset: u64,
+    },
+    /// The hunk starting at `offset` isn't terminated by a 0 byte before the checksums
+    UnterminatedHunk {
+        offset: u64,
+    },
+    /// There is data at `offset` after the point where the patch should have ended
+    UnexpectedData {
+        offset: u64,
+    },
+    /// The given IPS file isn't actually an IPS patch file
+    IsNotIpsFile,
+    /// The given BPS file isn't actually a BPS patch file
+    IsNotBpsFile,
+    /// The given file isn't a patch file of any known format
+    IsNotPatchFile,
+    /// The given file is a patch file of a format that is recognised but can't be loaded
+    UnsupportedFormat,
     /// Any other error
     Unknown
 }
 /// Errors that happen when applying a patch to a file
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ApplyError{
     /// The provided source file doesn't match the patch
-    SourceMismatch,
+    SourceMismatch {
+        /// The crc32 the patch expects for the source file
+        expected: u32,
+        /// The crc32 of the provided file
+        actual: u32,
+    },
+    /// The provided source file doesn't have the size the patch expects
+    SourceSizeMismatch {
+        /// The size the patch expects for the source file
+        expected: u64,
+        /// The size of the provided file
+        actual: u64,
+    },
     /// The result after patching a valid source file doesn't match
-    TargetMismatch,
+    TargetMismatch {
+        /// The crc32 the patch expects for the target file
+        expected: u32,
+        /// The crc32 of the patched file
+        actual: u32,
+    },
+    /// The patch produces a file of a different size than the one it says the target has
+    TargetSizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The patch declares a file of `size` bytes, which is too big to be held in memory
+    TooBig {
+        size: u64,
+    },
+    /// The patch tries to read or write outside of the files, while writing the target file at
+    /// `offset`
+    OutOfBounds {
+        offset: u64,
+    },
     /// Any other error
     Unknown
 }
 /// Errors that happen when creating a file from source and target files
-///
-///
-/// there is no way for a ups error to actually be a create error at the moment, but in the future
-/// it may be necessary
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum CreateError{
-
+    /// A hunk given to [`UpsWriter`](crate::UpsWriter) at `offset` overlaps the previous one or
+    /// contains a 0 byte, so it can't be written
+    InvalidHunk {
+        offset: u64,
+    },
+    /// The target file is too big to be represented in the patch format
+    FileTooBig {
+        size: u64,
+        max_size: u64,
+    },
+    /// The patches given to [`UpsPatch::compose`](crate::UpsPatch::compose) can't be applied one
+    /// after the other, as the target of the first one isn't the source of the second one
+    IncompatiblePatches {
+        /// The crc32 of the source file of the second patch
+        expected_crc32: u32,
+        /// The crc32 of the target file of the first patch
+        actual_crc32: u32,
+        /// The size of the source file of the second patch
+        expected_size: u64,
+        /// The size of the target file of the first patch
+        actual_size: u64,
+    },
+    /// Any other error
     Unknown
 }
 
 impl fmt::Display for UpsError{
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Ups error: {}", self.message())
+        match self {
+            UpsError::Load(load_error) => write!(f, "Ups error: {}", load_error),
+            UpsError::Apply(apply_error) => write!(f, "Ups error: {}", apply_error),
+            UpsError::Create(create_error) => write!(f, "Ups error: {}", create_error),
+            UpsError::Io(io_error) => write!(f, "Ups error: {}: {}", self.message(), io_error),
+        }
+    }
+}
+impl error::Error for UpsError{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            UpsError::Io(io_error) => Some(io_error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadError{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::IsCorrupted { expected, actual } =>
+                write!(f, "{} (expected {:08x}, found {:08x})", self.message(), expected, actual),
+            LoadError::Truncated { offset }
+            | LoadError::UnterminatedVarint { offset }
+            | LoadError::VarintOverflow { offset }
+            | LoadError::UnterminatedHunk { offset }
+            | LoadError::UnexpectedData { offset } => write!(f, "{} at byte {}", self.message(), offset),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl fmt::Display for ApplyError{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::SourceMismatch { expected, actual }
+            | ApplyError::TargetMismatch { expected, actual } =>
+                write!(f, "{} (expected {:08x}, found {:08x})", self.message(), expected, actual),
+            ApplyError::SourceSizeMismatch { expected, actual }
+            | ApplyError::TargetSizeMismatch { expected, actual } =>
+                write!(f, "{} (expected {} bytes, found {} bytes)", self.message(), expected, actual),
+            ApplyError::TooBig { size } => write!(f, "{} ({} bytes)", self.message(), size),
+            ApplyError::OutOfBounds { offset } => write!(f, "{} at target byte {}", self.message(), offset),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl fmt::Display for CreateError{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::InvalidHunk { offset } => write!(f, "{} at byte {}", self.message(), offset),
+            CreateError::FileTooBig { size, max_size } =>
+                write!(f, "{} ({} bytes, the maximum is {} bytes)", self.message(), size, max_size),
+            CreateError::IncompatiblePatches { expected_crc32, actual_crc32, expected_size, actual_size } =>
+                write!(f, "{} (expected {:08x} of {} bytes, found {:08x} of {} bytes)",
+                       self.message(), expected_crc32, expected_size, actual_crc32, actual_size),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+/// I/O errors are compared by their kind, as `io::Error` itself can't be compared
+impl PartialEq for UpsError{
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (UpsError::Load(a), UpsError::Load(b)) => a == b,
+            (UpsError::Apply(a), UpsError::Apply(b)) => a == b,
+            (UpsError::Create(a), UpsError::Create(b)) => a == b,
+            (UpsError::Io(a), UpsError::Io(b)) => a.kind() == b.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for UpsError{
+    fn from(io_error: io::Error) -> Self {
+        UpsError::Io(io_error)
+    }
+}
+
+impl From<LoadError> for UpsError{
+    fn from(load_error: LoadError) -> Self {
+        UpsError::Load(load_error)
     }
 }
-impl error::Error for UpsError{}
 
 impl UpsError{
+    /// A short description of the error, without the details carried by it
     pub fn message(&self) -> &'static str {
         match self {
-            UpsError::Load(load_error) => {match load_error {
-                LoadError::IsNotUpsFile => "File provided is not a UPS Patch file",
-                LoadError::IsCorrupted => "FIle provided apears to be corrupted, doesn't match crc32",
-                _ => "Unknown error during patch load"
-            }}
-            UpsError::Apply(apply_error) => { match apply_error {
-                ApplyError::SourceMismatch => "Source file doesn't match crc32 for source file",
-                ApplyError::TargetMismatch => "Final target file doesn't match crc32 for target file",
-                _ => "Unknown error during patch apply"
-            }}
-            UpsError::Create(create_error) => {"Unknown Error during patch creation"}
+            UpsError::Load(load_error) => load_error.message(),
+            UpsError::Apply(apply_error) => apply_error.message(),
+            UpsError::Create(create_error) => create_error.message(),
+            UpsError::Io(_) => "Couldn't read or write a file",
         }
     }
-}
\ No newline at end of file
+}
+
+impl LoadError{
+    /// A short description of the error, without the details carried by it
+    pub fn message(&self) -> &'static str {
+        match self {
+            LoadError::IsNotUpsFile => "File provided is not a UPS Patch file",
+            LoadError::IsCorrupted { .. } => "File provided appears to be corrupted, doesn't match crc32",
+            LoadError::Truncated { .. } => "File provided ends before the patch does",
+            LoadError::UnterminatedVarint { .. } => "File provided ends in the middle of a number",
+            LoadError::VarintOverflow { .. } => "File provided contains a number too big to be handled",
+            LoadError::UnterminatedHunk { .. } => "File provided contains a hunk that doesn't end",
+            LoadError::UnexpectedData { .. } => "File provided contains data after the end of the patch",
+            LoadError::IsNotIpsFile => "File provided is not an IPS Patch file",
+            LoadError::IsNotBpsFile => "File provided is not a BPS Patch file",
+            LoadError::IsNotPatchFile => "File provided is not a Patch file of any known format",
+            LoadError::UnsupportedFormat => "File provided is a Patch file of an unsupported format",
+            LoadError::Unknown => "Unknown error during patch load",
+        }
+    }
+}
+
+impl ApplyError{
+    /// A short description of the error, without the details carried by it
+    pub fn message(&self) -> &'static str {
+        match self {
+            ApplyError::SourceMismatch { .. } => "Source file doesn't match crc32 for source file",
+            ApplyError::SourceSizeMismatch { .. } => "Source file doesn't have the size of the source file",
+            ApplyError::TargetMismatch { .. } => "Final target file doesn't match crc32 for target file",
+            ApplyError::TargetSizeMismatch { .. } => "Final target file doesn't have the size of the target file",
+            ApplyError::TooBig { .. } => "Patch declares a file too big to be held in memory",
+            ApplyError::OutOfBounds { .. } => "Patch tries to access data outside of the files",
+            ApplyError::Unknown => "Unknown error during patch apply",
+        }
+    }
+}
+
+impl CreateError{
+    /// A short description of the error, without the details carried by it
+    pub fn message(&self) -> &'static str {
+        match self {
+            CreateError::InvalidHunk { .. } => "Hunk overlaps the previous one or contains a 0 byte",
+            CreateError::FileTooBig { .. } => "Target file is too big for the patch format",
+            CreateError::IncompatiblePatches { .. } => "Target file of the first patch isn't the source file of the second one",
+            CreateError::Unknown => "Unknown Error during patch creation",
+        }
+    }
+}
@@ -1,13 +1,55 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 use crate::{
     crc32,
+    crc32::Crc32,
+    varint,
+    ApplyOptions,
+    CreateOptions,
+    HeaderFixup,
     UpsError,
+    UpsWriter,
     LoadError::*,
-    CreateError::*,
     ApplyError::*,
+    CreateError::*,
 };
-use std::cmp::max;
+use std::cmp::{max, min};
+
+/// Direction in which a patch gets applied
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    /// From the source file to the target file
+    Forward,
+    /// From the target file back to the source file
+    Reverse,
+}
+
+/// What a file turned out to be when checked against a patch
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileVerdict {
+    /// The file is the source file of the patch
+    Source,
+    /// The file is the target file of the patch
+    Target,
+    /// The file doesn't have the size of the file it was checked against
+    SizeMismatch {
+        /// The size of the file in the patch
+        expected: u64,
+        /// The size of the given file
+        actual: u64,
+    },
+    /// The file has the size of the file it was checked against, but not its crc32
+    Crc32Mismatch {
+        /// The crc32 of the file in the patch
+        expected: u32,
+        /// The crc32 of the given file
+        actual: u32,
+    },
+}
+
 ///Represents a  ups patch
 #[derive(Debug, Eq, PartialEq)]
 pub struct UpsPatch {
@@ -34,16 +76,21 @@ pub struct UpsPatch {
 }
 
 impl UpsPatch {
-    const CANON_HEADER: [u8; 4] = [0x55, 0x50, 0x53, 0x31];
+    pub(crate) const CANON_HEADER: [u8; 4] = [0x55, 0x50, 0x53, 0x31];
+    /// Amount of bytes held in memory at once by the streaming functions
+    const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+    /// Size of the pieces the files are split into to compare them in parallel
+    #[cfg(feature = "rayon")]
+    const PARALLEL_CHUNK_SIZE: u64 = 1 << 20;
 
     /// Creates a Patch from the given source and target files
     /// # Arguments
     /// * `source_content` - The contents of the source/original file
     /// * `target_content` - The contents of the target/final file
     ///
-    pub fn create(source_content: &Vec<u8>, target_content: &Vec<u8>) -> UpsPatch {
-        let source_crc32 = crc32::calculate(&*source_content);
-        let target_crc32 = crc32::calculate(&*target_content);
+    pub fn create(source_content: &[u8], target_content: &[u8]) -> UpsPatch {
+        let source_crc32 = crc32::calculate(source_content);
+        let target_crc32 = crc32::calculate(target_content);
 
         let source_file_size = source_content.len() as u64;
         let target_file_size = target_content.len() as u64;
@@ -56,7 +103,7 @@ impl UpsPatch {
             let mut x: u8 = if i < source_file_size { source_content[i as usize] } else { 0x00 };
             let mut y: u8 = if i < target_file_size { target_content[i as usize] } else { 0x00 };
             if x != y {
-                let change_offset = i as u64;
+                let change_offset = i;
                 let mut changed_bytes: Vec<u8> = vec![];
                 while x != y && i < max_size {
                     changed_bytes.push(x ^ y);
@@ -69,22 +116,273 @@ impl UpsPatch {
             i += 1
         }
 
-        let bytearray = UpsPatch::tailless_bytearray(source_file_size,
-                                                     target_file_size,
-                                                     &changes,
-                                                     source_crc32,
-                                                     target_crc32);
-        let patch_crc32 = crc32::calculate(&*bytearray);
+        let mut patch = UpsPatch {
+            source_file_size,
+            target_file_size,
+            changes,
+            source_crc32,
+            target_crc32,
+            patch_crc32: 0,
+        };
+        patch.update_patch_crc32();
+        patch
+    }
+
+    /// Combines two patches that are applied one after the other into a single one, so that
+    /// `compose(a, b).apply(source) == b.apply(&a.apply(source)?)` without needing the file in
+    /// between.
+    ///
+    /// The XOR bytes of both patches are merged, and the ones past the end of both the source of
+    /// `first` and the target of `second` are dropped. This relies on each patch changing the
+    /// bytes past the end of its files into zeros, as the patches created by this library do
+    /// unless [`drop_source_tail`](CreateOptions::drop_source_tail) is set. When `first` was
+    /// created with it and `second` makes the file longer than the target of `first`, applying
+    /// the result fails with [`TargetMismatch`](crate::ApplyError::TargetMismatch).
+    ///
+    /// Returns an error if the target file of `first` isn't the source file of `second`.
+    /// # Arguments
+    /// * `first` - The patch that goes from the original file to the intermediate one
+    /// * `second` - The patch that goes from the intermediate file to the final one
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # let v1_to_v2 = UpsPatch::load(&vec![]).unwrap();
+    /// # let v2_to_v3 = UpsPatch::load(&vec![]).unwrap();
+    /// let v1_to_v3 = UpsPatch::compose(&v1_to_v2, &v2_to_v3).unwrap();
+    /// ```
+    pub fn compose(first: &UpsPatch, second: &UpsPatch) -> Result<UpsPatch, UpsError> {
+        if first.target_crc32 != second.source_crc32 || first.target_file_size != second.source_file_size {
+            return Err(UpsError::Create(IncompatiblePatches {
+                expected_crc32: second.source_crc32,
+                actual_crc32: first.target_crc32,
+                expected_size: second.source_file_size,
+                actual_size: first.target_file_size,
+            }));
+        }
+
+        let mut patch = UpsPatch {
+            source_file_size: first.source_file_size,
+            target_file_size: second.target_file_size,
+            changes: first.changes.iter().chain(&second.changes).cloned().collect(),
+            source_crc32: first.source_crc32,
+            target_crc32: second.target_crc32,
+            patch_crc32: 0,
+        };
+        let size = max(patch.source_file_size, patch.target_file_size);
+        patch.changes = patch.canonical_changes().into_iter()
+            .filter(|(offset, _)| *offset < size)
+            .map(|(offset, mut xor_bytes)| {
+                xor_bytes.truncate(min(xor_bytes.len() as u64, size - offset) as usize);
+                (offset, xor_bytes)
+            })
+            .collect();
+        patch.update_patch_crc32();
+        Ok(patch)
+    }
+
+    /// Returns the patch that goes from the target file back to the source file
+    ///
+    /// Any patch can already be reverted with [`revert`](UpsPatch::revert), but not every tool
+    /// supports it, so this gives a patch that all of them can apply to get the source back.
+    /// The XOR bytes are the same in both directions, so only the sizes and checksums change,
+    /// which is why the result can't be applied if this patch was created with
+    /// [`drop_source_tail`](CreateOptions::drop_source_tail) from a source that didn't end in zeros.
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// let unpatch_file_content = patch.invert().get_patch_file_contents();
+    /// ```
+    pub fn invert(&self) -> UpsPatch {
+        let mut patch = UpsPatch {
+            source_file_size: self.target_file_size,
+            target_file_size: self.source_file_size,
+            changes: self.changes.clone(),
+            source_crc32: self.target_crc32,
+            target_crc32: self.source_crc32,
+            patch_crc32: 0,
+        };
+        patch.update_patch_crc32();
+        patch
+    }
+
+    /// Sets `patch_crc32` to the checksum of the patch file that gets written for this patch
+    fn update_patch_crc32(&mut self) {
+        let patch_file_content = self.get_patch_file_contents();
+        let l = patch_file_content.len();
+        self.patch_crc32 = u32::from_le_bytes(patch_file_content[l - 4..l].try_into().unwrap());
+    }
+
+    /// Creates a Patch from the given source and target files, choosing how with `options`
+    /// # Arguments
+    /// * `source_content` - The contents of the source/original file
+    /// * `target_content` - The contents of the target/final file
+    /// * `options` - How to create the patch
+    pub fn create_with_options(source_content: &[u8], target_content: &[u8], options: &CreateOptions) -> UpsPatch {
+        #[cfg(feature = "rayon")]
+        let mut patch = if options.parallel {
+            UpsPatch::create_parallel(source_content, target_content)
+        } else {
+            UpsPatch::create(source_content, target_content)
+        };
+        #[cfg(not(feature = "rayon"))]
+        let mut patch = UpsPatch::create(source_content, target_content);
+
+        if options.drop_source_tail && patch.target_file_size < patch.source_file_size {
+            let target_file_size = patch.target_file_size;
+            patch.changes.retain(|(offset, _)| *offset < target_file_size);
+            for (offset, xor_bytes) in &mut patch.changes {
+                xor_bytes.truncate(min(xor_bytes.len() as u64, target_file_size - *offset) as usize);
+            }
+            patch.update_patch_crc32();
+        }
+        patch
+    }
+
+    /// Creates a Patch from the given source and target files using all the available cores,
+    /// giving exactly the same patch as [`create`](UpsPatch::create) does.
+    ///
+    /// The files are split in chunks that are compared in parallel, joining the changes that
+    /// cross from one chunk to the next afterwards, and their crc32 checksums are calculated
+    /// in parallel too.
+    /// # Arguments
+    /// * `source_content` - The contents of the source/original file
+    /// * `target_content` - The contents of the target/final file
+    #[cfg(feature = "rayon")]
+    pub fn create_parallel(source_content: &[u8], target_content: &[u8]) -> UpsPatch {
+        use rayon::prelude::*;
+
+        let (source_crc32, target_crc32) = rayon::join(
+            || crc32::calculate_parallel(source_content),
+            || crc32::calculate_parallel(target_content),
+        );
+        let source_file_size = source_content.len() as u64;
+        let target_file_size = target_content.len() as u64;
+        let max_size = max(source_file_size, target_file_size);
+        let chunk_count = max_size.div_ceil(UpsPatch::PARALLEL_CHUNK_SIZE);
+
+        let chunk_changes: Vec<Vec<(u64, Vec<u8>)>> = (0..chunk_count).into_par_iter()
+            .map(|chunk| {
+                let start = chunk * UpsPatch::PARALLEL_CHUNK_SIZE;
+                let end = min(start + UpsPatch::PARALLEL_CHUNK_SIZE, max_size);
+                UpsPatch::diff_range(source_content, target_content, start, end)
+            })
+            .collect();
+
+        let mut changes: Vec<(u64, Vec<u8>)> = vec![];
+        for (offset, xor_bytes) in chunk_changes.into_iter().flatten() {
+            match changes.last_mut() {
+                // A change that reaches the end of its chunk continues in the next one
+                Some((last_offset, last_bytes)) if *last_offset + last_bytes.len() as u64 == offset => {
+                    last_bytes.extend(xor_bytes);
+                }
+                _ => changes.push((offset, xor_bytes)),
+            }
+        }
 
-        UpsPatch {
+        let mut patch = UpsPatch {
             source_file_size,
             target_file_size,
             changes,
             source_crc32,
             target_crc32,
-            patch_crc32,
+            patch_crc32: 0,
+        };
+        patch.update_patch_crc32();
+        patch
+    }
+
+    /// Returns the runs of different bytes between `start` and `end`, with the files padded with
+    /// zeros to the same size
+    #[cfg(feature = "rayon")]
+    fn diff_range(source_content: &[u8], target_content: &[u8], start: u64, end: u64) -> Vec<(u64, Vec<u8>)> {
+        let byte_at = |content: &[u8], i: u64| content.get(i as usize).copied().unwrap_or(0);
+        let mut changes: Vec<(u64, Vec<u8>)> = vec![];
+        let mut changed_bytes: Vec<u8> = vec![];
+        for i in start..end {
+            let xor_byte = byte_at(source_content, i) ^ byte_at(target_content, i);
+            if xor_byte != 0 {
+                changed_bytes.push(xor_byte);
+            } else if !changed_bytes.is_empty() {
+                changes.push((i - changed_bytes.len() as u64, std::mem::take(&mut changed_bytes)));
+            }
         }
+        if !changed_bytes.is_empty() {
+            changes.push((end - changed_bytes.len() as u64, changed_bytes));
+        }
+        changes
     }
+
+    /// Creates a patch reading the source and target files from `source` and `target`, and writes
+    /// it into `patch` as it is generated, so that only a small buffer of each file is kept in memory.
+    ///
+    /// Both files are read in lock-step from their beginning, and each hunk is written as soon as
+    /// it ends. The files need to be seekable because their sizes are part of the header of the patch.
+    /// # Arguments
+    /// * `source` - The source/original file
+    /// * `target` - The target/final file
+    /// * `patch` - Where the patch gets written
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # use std::fs::File;
+    /// # use std::io::BufWriter;
+    /// let mut source = File::open("path/to/source/file").unwrap();
+    /// let mut target = File::open("path/to/target/file").unwrap();
+    /// let mut patch = BufWriter::new(File::create("path/to/patch/file").unwrap());
+    /// UpsPatch::create_stream(&mut source, &mut target, &mut patch).unwrap();
+    /// ```
+    pub fn create_stream<S, T, W>(source: &mut S, target: &mut T, patch: &mut W) -> Result<(), UpsError>
+        where S: Read + Seek,
+              T: Read + Seek,
+              W: Write {
+        let source_file_size = source.seek(SeekFrom::End(0))?;
+        let target_file_size = target.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(0))?;
+        target.seek(SeekFrom::Start(0))?;
+        let max_size = max(source_file_size, target_file_size);
+
+        let mut writer = UpsWriter::new(patch, source_file_size, target_file_size)?;
+        let mut source_crc32 = Crc32::new();
+        let mut target_crc32 = Crc32::new();
+        let mut source_buffer = vec![0u8; UpsPatch::STREAM_BUFFER_SIZE];
+        let mut target_buffer = vec![0u8; UpsPatch::STREAM_BUFFER_SIZE];
+        let mut change_offset: u64 = 0;
+        let mut changed_bytes: Vec<u8> = vec![];
+        let mut position: u64 = 0;
+        while position < max_size {
+            let source_read = UpsPatch::read_chunk(source, &mut source_buffer)?;
+            let target_read = UpsPatch::read_chunk(target, &mut target_buffer)?;
+            source_crc32.update(&source_buffer[..source_read]);
+            target_crc32.update(&target_buffer[..target_read]);
+            for byte in &mut source_buffer[source_read..] {
+                *byte = 0;
+            }
+            for byte in &mut target_buffer[target_read..] {
+                *byte = 0;
+            }
+
+            for (i, (x, y)) in source_buffer.iter().zip(&target_buffer).enumerate() {
+                if x != y {
+                    if changed_bytes.is_empty() {
+                        change_offset = position + i as u64;
+                    }
+                    changed_bytes.push(x ^ y);
+                } else if !changed_bytes.is_empty() {
+                    writer.write_hunk(change_offset, &changed_bytes)?;
+                    changed_bytes.clear();
+                }
+            }
+            position += source_buffer.len() as u64;
+        }
+        if !changed_bytes.is_empty() {
+            writer.write_hunk(change_offset, &changed_bytes)?;
+        }
+
+        writer.finish(source_crc32.finish(), target_crc32.finish())?;
+        Ok(())
+    }
+
     /// Loads an already existing patch, if the given file contents don't contain a valid UPS patch returns a UpsError
     /// # Arguments
     /// * `content` - The content of the patch file to load
@@ -100,48 +398,47 @@ impl UpsPatch {
     ///
     /// ```
     ///
-    pub fn load(content: &Vec<u8>) -> Result<UpsPatch, UpsError> {
-        if content[0..4] != UpsPatch::CANON_HEADER {
+    pub fn load(content: &[u8]) -> Result<UpsPatch, UpsError> {
+        if !content.starts_with(&UpsPatch::CANON_HEADER) {
             return Err(UpsError::Load(IsNotUpsFile));
         }
         let l = content.len();
+        if l < UpsPatch::CANON_HEADER.len() + 12 {
+            return Err(UpsError::Load(Truncated { offset: 0 }));
+        }
         let patch_crc32 = u32::from_le_bytes(content[l - 4..l].try_into().unwrap());
         let patch_computed_crc32 = crc32::calculate(&content[0..l - 4]);
 
         if patch_computed_crc32 != patch_crc32 {
-            return Err(UpsError::Load(IsCorrupted));
+            return Err(UpsError::Load(IsCorrupted { expected: patch_crc32, actual: patch_computed_crc32 }));
         }
         let source_crc32 = u32::from_le_bytes(content[l - 12..l - 8].try_into().unwrap());
         let target_crc32 = u32::from_le_bytes(content[l - 8..l - 4].try_into().unwrap());
 
-        let mut i: usize = 4;
+        // Everything is read from the body only, so nothing can run into the checksums
+        let body = &content[..l - 12];
+        let read = |i: usize| varint::decode(body, i).map_err(UpsError::Load);
 
-        let (new_i, source_file_size) = UpsPatch::find_pointer(&content, i);
-        i = new_i;
-        let (new_i, target_file_size) = UpsPatch::find_pointer(&content, i);
-        i = new_i;
+        let i = UpsPatch::CANON_HEADER.len();
+        let (source_file_size, i) = read(i)?;
+        let (target_file_size, mut i) = read(i)?;
 
         let mut changes: Vec<(u64, Vec<u8>)> = vec![];
-        let mut offset_diffs: Vec<u64> = vec![];
-        let mut xor_bytes_vec: Vec<Vec<u8>> = vec![];
-        while i < l - 13 {
-            let (new_i, offset_dif) = UpsPatch::find_pointer(&content, i);
-            i = new_i;
-            let mut xor_bytes: Vec<u8> = vec![];
-            while content[i] != 0 {
-                xor_bytes.push(content[i]);
-                i += 1;
-            }
-            offset_diffs.push(offset_dif);
-            xor_bytes_vec.push(xor_bytes);
-        }
-        for ((i, offset_dif), xor_bytes) in offset_diffs.iter().enumerate().zip(xor_bytes_vec) {
-            let offset = offset_dif + if changes.len() > 0 {
-                changes[i-1].0 + changes[i-1].1.len() as u64 +1
-            } else { 0 };
-            changes.push( (offset, xor_bytes))
-        }
+        let mut next_hunk_base: u64 = 0;
+        while i < body.len() {
+            let hunk_start = i;
+            let overflow = || UpsError::Load(VarintOverflow { offset: hunk_start as u64 });
+            let (relative_offset, next_i) = read(i)?;
+            i = next_i;
+            let hunk_length = body[i..].iter().position(|byte| *byte == 0)
+                .ok_or(UpsError::Load(UnterminatedHunk { offset: hunk_start as u64 }))?;
+            let xor_bytes = body[i..i + hunk_length].to_vec();
+            i += hunk_length + 1;
 
+            let offset = next_hunk_base.checked_add(relative_offset).ok_or_else(overflow)?;
+            next_hunk_base = offset.checked_add(hunk_length as u64 + 1).ok_or_else(overflow)?;
+            changes.push((offset, xor_bytes));
+        }
 
         let file = UpsPatch {
             source_file_size,
@@ -156,175 +453,591 @@ impl UpsPatch {
 
     /// Given the contents of a file, verifies that it is the expected source for the patch,
     /// applies the patch and verifies that the output is the expected target for the patch.
+    ///
+    /// UPS patches are bi-directional, so if the given file is the target of the patch instead,
+    /// the patch is reverted and the original source file is returned.
+    ///
+    /// Both the size and the crc32 of the files are checked, a file of the wrong size is reported
+    /// as [`SourceSizeMismatch`](crate::ApplyError::SourceSizeMismatch) before its crc32 is
+    /// compared.
     /// # Arguments
-    /// * `source` - The content of the source file
+    /// * `source` - The content of the source file, or of the target file to get the source back
     /// # Examples
     /// Load a patch, apply it and save to a variable if everything is ok or panic if something went wrong
     ///
-    pub fn apply(&self, source:&Vec<u8>) -> Result<Vec<u8>, UpsError>{
-        if !self.file_is_source(&source) {
-            return Err(UpsError::Apply(SourceMismatch))
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>, UpsError>{
+        self.apply_with_options(source, &ApplyOptions::default())
+    }
+
+    /// Works like [`apply`](UpsPatch::apply), with the checks made along the way changed by
+    /// `options`
+    /// # Arguments
+    /// * `source` - The content of the source file, or of the target file to get the source back
+    /// * `options` - Which extra checks to make
+    /// # Examples
+    /// ```no_run
+    /// # use ups::{ApplyOptions, UpsPatch};
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// # let source_file_content: Vec<u8> = vec![];
+    /// let options = ApplyOptions { strict: true, ..ApplyOptions::default() };
+    /// let patched_file_content = patch.apply_with_options(&source_file_content, &options).unwrap();
+    /// ```
+    pub fn apply_with_options(&self, source: &[u8], options: &ApplyOptions) -> Result<Vec<u8>, UpsError> {
+        self.apply_with_fixup(source, options).map(|(output, _)| output)
+    }
+
+    /// Works like [`apply_with_options`](UpsPatch::apply_with_options), also returning the
+    /// header fixup that had to be made to the file, if any.
+    ///
+    /// When [`fix_headers`](ApplyOptions::fix_headers) is set and the given file is neither the
+    /// source nor the target of the patch, it is tried again without the SNES copier, iNES or
+    /// fwNES header it has, or with one of those headers added when the patch expects a file
+    /// with it. The result is given in the same form as the given file: a removed header is put
+    /// back at its start, and an added one is removed from it. If no fixup works, the error for
+    /// the file as it was given is returned.
+    /// # Arguments
+    /// * `source` - The content of the source file, or of the target file to get the source back
+    /// * `options` - Which extra checks and fixups to make
+    /// # Examples
+    /// ```no_run
+    /// # use ups::{ApplyOptions, UpsPatch};
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// # let rom_content: Vec<u8> = vec![];
+    /// let options = ApplyOptions { fix_headers: true, ..ApplyOptions::default() };
+    /// let (patched_rom_content, fixup) = patch.apply_with_fixup(&rom_content, &options).unwrap();
+    /// if let Some(fixup) = fixup {
+    ///     println!("The ROM had to be fixed: {:?}", fixup);
+    /// }
+    /// ```
+    pub fn apply_with_fixup(&self, source: &[u8], options: &ApplyOptions) -> Result<(Vec<u8>, Option<HeaderFixup>), UpsError> {
+        let result = self.apply_checked(source, options);
+        if let Err(UpsError::Apply(SourceMismatch { .. })) | Err(UpsError::Apply(SourceSizeMismatch { .. })) = result {
+            if options.fix_headers {
+                if let Some((fixup, fixed_source)) = self.find_header_fixup(source) {
+                    let output = self.apply_in_direction(&fixed_source, Direction::Forward)?;
+                    return Ok((fixup.restore(source, output), Some(fixup)));
+                }
+            }
         }
-        let target = self.apply_no_check(&source);
-        if !self.file_is_target(&target) {
-            return Err(UpsError::Apply(TargetMismatch))
+        result.map(|output| (output, None))
+    }
+
+    /// Applies the patch in whichever direction `source` matches, making the checks in `options`
+    fn apply_checked(&self, source: &[u8], options: &ApplyOptions) -> Result<Vec<u8>, UpsError> {
+        if options.strict {
+            self.check_hunk_bounds()?;
         }
-        Ok(target)
+        match self.direction_for(source) {
+            Some(direction) => self.apply_in_direction(source, direction),
+            None if source.len() as u64 != self.source_file_size => Err(UpsError::Apply(SourceSizeMismatch {
+                expected: self.source_file_size,
+                actual: source.len() as u64,
+            })),
+            None => Err(UpsError::Apply(SourceMismatch {
+                expected: self.source_crc32,
+                actual: crc32::calculate(source),
+            })),
+        }
+    }
 
+    /// Returns an error if any hunk changes bytes past the end of both files, which a patch made
+    /// from actual files never does
+    fn check_hunk_bounds(&self) -> Result<(), UpsError> {
+        let max_size = max(self.source_file_size, self.target_file_size);
+        for (offset, xor_bytes) in &self.changes {
+            if offset.saturating_add(xor_bytes.len() as u64) > max_size {
+                return Err(UpsError::Apply(OutOfBounds { offset: max(*offset, max_size) }));
+            }
+        }
+        Ok(())
     }
+
+    /// Given the contents of the target file of the patch, reverts the patch to get the source
+    /// file back, verifying both files along the way.
+    /// # Arguments
+    /// * `target` - The content of the target file
+    pub fn revert(&self, target: &[u8]) -> Result<Vec<u8>, UpsError> {
+        self.apply_in_direction(target, Direction::Reverse)
+    }
+
+    /// Applies the patch in the given direction, verifying that the given file is the expected
+    /// input for that direction and that the output is the expected output for it.
+    ///
+    /// [`SourceMismatch`](crate::ApplyError::SourceMismatch),
+    /// [`SourceSizeMismatch`](crate::ApplyError::SourceSizeMismatch) and
+    /// [`TargetMismatch`](crate::ApplyError::TargetMismatch) refer to the input and the output
+    /// of the operation, so when reverting they are about the target and source files respectively.
+    /// # Arguments
+    /// * `content` - The content of the file to patch
+    /// * `direction` - Whether to go from source to target or from target to source
+    pub fn apply_in_direction(&self, content: &[u8], direction: Direction) -> Result<Vec<u8>, UpsError> {
+        let (input_size, input_crc32, output_crc32) = match direction {
+            Direction::Forward => (self.source_file_size, self.source_crc32, self.target_crc32),
+            Direction::Reverse => (self.target_file_size, self.target_crc32, self.source_crc32),
+        };
+        if content.len() as u64 != input_size {
+            return Err(UpsError::Apply(SourceSizeMismatch { expected: input_size, actual: content.len() as u64 }))
+        }
+        let content_crc32 = crc32::calculate(content);
+        if content_crc32 != input_crc32 {
+            return Err(UpsError::Apply(SourceMismatch { expected: input_crc32, actual: content_crc32 }))
+        }
+        let output = match direction {
+            Direction::Forward => self.apply_no_check(content)?,
+            Direction::Reverse => self.revert_no_check(content)?,
+        };
+        let result_crc32 = crc32::calculate(&output);
+        if result_crc32 != output_crc32 {
+            return Err(UpsError::Apply(TargetMismatch { expected: output_crc32, actual: result_crc32 }))
+        }
+        Ok(output)
+    }
+
+    /// Returns in which direction the patch has to be applied to the given file, or `None` if the
+    /// file is neither the source nor the target of the patch.
+    pub fn direction_for(&self, content: &[u8]) -> Option<Direction> {
+        if self.file_is_source(content) {
+            Some(Direction::Forward)
+        } else if self.file_is_target(content) {
+            Some(Direction::Reverse)
+        } else {
+            None
+        }
+    }
+
     /// Applies a patch to a given source file contents.
     /// This function doesn't check for file to actually be the correct source file, it just
-    /// applies the patch.
-    pub fn apply_no_check(&self, source: &Vec<u8>) -> Vec<u8> {
-        let mut output: Vec<u8> = source.clone();
-        output.resize(max(self.source_file_size, self.target_file_size)as usize, 0);
-        for change in &self.changes {
-            for (i, xor_byte) in change.1.iter().enumerate() {
-                output[change.0 as usize+i] ^= xor_byte;
+    /// applies the patch. A source shorter than the patch expects is padded with zeros, and a
+    /// longer one gets truncated. It still fails if the patch declares files too big to be held
+    /// in memory.
+    pub fn apply_no_check(&self, source: &[u8]) -> Result<Vec<u8>, UpsError> {
+        self.xor_changes(source, self.target_file_size)
+    }
+
+    /// Reverts a patch from the given target file contents, getting the source file back.
+    /// This function doesn't check for file to actually be the correct target file, it just
+    /// reverts the patch, failing only if the patch declares files too big to be held in memory.
+    pub fn revert_no_check(&self, target: &[u8]) -> Result<Vec<u8>, UpsError> {
+        self.xor_changes(target, self.source_file_size)
+    }
+
+    /// XORs the changes into `input`, which works both ways as long as the input is padded to
+    /// the biggest of both files and the output gets truncated to its own size.
+    ///
+    /// The sizes come from the patch, so the memory for them is reserved fallibly, returning an
+    /// error instead of aborting when they are too big. They can't be bounded by the size of the
+    /// patch, as the zeros at the end of a target bigger than its source don't take any space in it.
+    fn xor_changes(&self, input: &[u8], output_size: u64) -> Result<Vec<u8>, UpsError> {
+        let padded_size = max(self.source_file_size, self.target_file_size);
+        let too_big = || UpsError::Apply(TooBig { size: padded_size });
+        let padded_len = usize::try_from(padded_size).map_err(|_| too_big())?;
+        let mut output: Vec<u8> = vec![];
+        output.try_reserve_exact(padded_len).map_err(|_| too_big())?;
+        output.extend_from_slice(&input[..min(input.len(), padded_len)]);
+        output.resize(padded_len, 0);
+        let output_len = output.len() as u64;
+        for (offset, xor_bytes) in &self.changes {
+            // Bytes past the end of both files can only come from a malformed patch, and would
+            // be truncated away anyway
+            if *offset >= output_len {
+                continue;
+            }
+            let end = min(offset.saturating_add(xor_bytes.len() as u64), output_len) as usize;
+            let start = *offset as usize;
+            for (byte, xor_byte) in output[start..end].iter_mut().zip(xor_bytes) {
+                *byte ^= xor_byte;
             }
         }
-        if output.len() > self.target_file_size as usize {
-            output = output[0..self.target_file_size as usize].to_owned()
+        output.truncate(output_size as usize);
+        Ok(output)
+    }
+
+    /// Applies the patch reading the source file from `source` and writing the result into `target`,
+    /// so that only a small buffer is kept in memory instead of the whole files.
+    ///
+    /// The crc32 checksums are calculated as the data goes through, so unlike
+    /// [`apply`](UpsPatch::apply) a mismatch is only detected at the end, after the result has
+    /// already been written to `target`. The size of the source is checked before anything gets
+    /// written though.
+    /// # Arguments
+    /// * `source` - The source file, it is read from the beginning regardless of its current position
+    /// * `target` - Where the patched file gets written
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # use std::fs::File;
+    /// # use std::io::BufWriter;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// let mut source = File::open("path/to/source/file").unwrap();
+    /// let mut target = BufWriter::new(File::create("path/to/target/file").unwrap());
+    /// patch.apply_stream(&mut source, &mut target).unwrap();
+    /// ```
+    pub fn apply_stream<R: Read + Seek, W: Write>(&self, source: &mut R, target: &mut W) -> Result<(), UpsError> {
+        UpsPatch::check_stream_size(source, self.source_file_size)?;
+        let hunks = self.changes.iter().map(|(offset, xor_bytes)| Ok((*offset, xor_bytes.as_slice())));
+        let (source_crc32, target_crc32) = UpsPatch::stream_hunks(hunks, self.target_file_size, source, target)?;
+
+        if source_crc32 != self.source_crc32 {
+            return Err(UpsError::Apply(SourceMismatch { expected: self.source_crc32, actual: source_crc32 }));
+        }
+        if target_crc32 != self.target_crc32 {
+            return Err(UpsError::Apply(TargetMismatch { expected: self.target_crc32, actual: target_crc32 }));
         }
+        Ok(())
+    }
 
-        return output;
+    /// Applies the patch directly into `file`, which has to contain the source file, so that it
+    /// ends up containing the target file without any copy of it being made.
+    ///
+    /// The file is resized to the biggest of the source and target sizes, each hunk is XORed into
+    /// it and then it is truncated to the target size. Only the changed bytes are written, but the
+    /// whole file is still read before and after to verify the crc32 checksums.
+    ///
+    /// If the file isn't the source the patch expects, it is left untouched. If the result isn't
+    /// the target, the file is restored to its original contents when `rollback` is set, by
+    /// XORing the hunks again, or left with the wrong result otherwise. An I/O error in the middle
+    /// of the process can leave the file partially patched.
+    /// # Arguments
+    /// * `file` - The source file, opened for both reading and writing
+    /// * `rollback` - Whether to restore the source file if the result doesn't match the target
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # use std::fs::OpenOptions;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// let mut file = OpenOptions::new().read(true).write(true).open("path/to/source/file").unwrap();
+    /// patch.apply_in_place(&mut file, true).unwrap();
+    /// ```
+    pub fn apply_in_place(&self, file: &mut File, rollback: bool) -> Result<(), UpsError> {
+        let original_size = file.seek(SeekFrom::End(0))?;
+        if original_size != self.source_file_size {
+            return Err(UpsError::Apply(SourceSizeMismatch { expected: self.source_file_size, actual: original_size }));
+        }
+        let source_crc32 = UpsPatch::file_crc32(file, original_size)?;
+        if source_crc32 != self.source_crc32 {
+            return Err(UpsError::Apply(SourceMismatch { expected: self.source_crc32, actual: source_crc32 }));
+        }
+
+        let padded_size = max(self.source_file_size, self.target_file_size);
+        file.set_len(padded_size)?;
+        self.xor_into_file(file, padded_size)?;
+        // The target is checked before truncating, so that the end of the source is still
+        // there in case it has to be rolled back
+        let target_crc32 = UpsPatch::file_crc32(file, self.target_file_size)?;
+        if target_crc32 == self.target_crc32 {
+            file.set_len(self.target_file_size)?;
+            return Ok(());
+        }
+
+        if rollback {
+            self.xor_into_file(file, padded_size)?;
+            file.set_len(original_size)?;
+        } else {
+            file.set_len(self.target_file_size)?;
+        }
+        Err(UpsError::Apply(TargetMismatch { expected: self.target_crc32, actual: target_crc32 }))
     }
-    fn tailless_bytearray(source_file_size: u64,
-                          target_file_size: u64,
-                          changes: &Vec<(u64, Vec<u8>)>,
-                          source_crc32: u32,
-                          target_crc32: u32, ) -> Vec<u8> {
-        let mut output: Vec<u8> = vec![];
-        output.extend(UpsPatch::CANON_HEADER);
-        output.extend(UpsPatch::encode(source_file_size));
-        output.extend(UpsPatch::encode(target_file_size));
-        for (i, (offset, xor_bytes)) in changes.iter().enumerate() {
-            let offset_to_encode : u64 = if i > 0 {
-                offset - (changes[i - 1].0 + changes[i - 1].1.len() as u64 + 1)
-            } else { *offset };
-            output.extend(UpsPatch::encode(offset_to_encode));
-            for byte in xor_bytes {
-                output.push(*byte)
+
+    /// Copies `source` into `target` xoring the given hunks on the way, and returns the crc32 of
+    /// the source and the target.
+    ///
+    /// Hunks have to be sorted by offset, which is always the case for the ones in a UPS file.
+    /// All of them are consumed even if they go past the end of both files, so that any error
+    /// the iterator has to report is not skipped.
+    pub(crate) fn stream_hunks<H, B, R, W>(hunks: H,
+                                           target_file_size: u64,
+                                           source: &mut R,
+                                           target: &mut W) -> Result<(u32, u32), UpsError>
+        where H: Iterator<Item = Result<(u64, B), UpsError>>,
+              B: AsRef<[u8]>,
+              R: Read + Seek,
+              W: Write {
+        let mut hunks = hunks;
+        source.seek(SeekFrom::Start(0))?;
+        let mut source_crc32 = Crc32::new();
+        let mut target_crc32 = Crc32::new();
+        let mut buffer = vec![0u8; UpsPatch::STREAM_BUFFER_SIZE];
+        let mut position: u64 = 0;
+        let mut current_hunk = hunks.next().transpose()?;
+        loop {
+            let read = UpsPatch::read_chunk(source, &mut buffer)?;
+            source_crc32.update(&buffer[..read]);
+            if read < buffer.len() && position >= target_file_size {
+                break;
             }
-            output.push(0x00)
+            for byte in &mut buffer[read..] {
+                *byte = 0;
+            }
+
+            let chunk_end = position + buffer.len() as u64;
+            while let Some((offset, xor_bytes)) = &current_hunk {
+                if *offset >= chunk_end {
+                    break;
+                }
+                let xor_bytes = xor_bytes.as_ref();
+                let hunk_end = offset + xor_bytes.len() as u64;
+                for i in max(*offset, position)..min(hunk_end, chunk_end) {
+                    buffer[(i - position) as usize] ^= xor_bytes[(i - offset) as usize];
+                }
+                if hunk_end > chunk_end {
+                    break;
+                }
+                current_hunk = hunks.next().transpose()?;
+            }
+
+            if position < target_file_size {
+                let output_len = min(target_file_size - position, buffer.len() as u64) as usize;
+                target.write_all(&buffer[..output_len])?;
+                target_crc32.update(&buffer[..output_len]);
+            }
+            position = chunk_end;
+        }
+        for hunk in hunks {
+            hunk?;
         }
-        output.extend(source_crc32.to_le_bytes());
-        output.extend(target_crc32.to_le_bytes());
-        return output;
+
+        Ok((source_crc32.finish(), target_crc32.finish()))
     }
-    /// Returns a vector with the contents of the patch.ups file
-    pub fn get_patch_file_contents(&self) -> Vec<u8> {
-        let mut output: Vec<u8> = vec![];
-        output.extend(UpsPatch::CANON_HEADER);
-        output.extend(UpsPatch::encode(self.source_file_size));
-        output.extend(UpsPatch::encode(self.target_file_size));
-        for (offset, xor_bytes) in &self.changes {
-            output.extend(UpsPatch::encode(*offset));
-            for byte in xor_bytes {
-                output.push(*byte);
+
+    /// Returns an error if `source` doesn't have `expected` bytes
+    pub(crate) fn check_stream_size<R: Seek>(source: &mut R, expected: u64) -> Result<(), UpsError> {
+        let actual = source.seek(SeekFrom::End(0))?;
+        if actual != expected {
+            return Err(UpsError::Apply(SourceSizeMismatch { expected, actual }));
+        }
+        Ok(())
+    }
+
+    /// Fills `buffer` as much as possible, returning less than its length only when the end of
+    /// `reader` is reached
+    fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, UpsError> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(UpsError::Io(e)),
             }
-            output.push(0x00)
         }
-        output.extend(self.source_crc32.to_le_bytes());
-        output.extend(self.target_crc32.to_le_bytes());
-        output.extend(self.patch_crc32.to_le_bytes());
-        return output;
+        Ok(filled)
     }
 
-    /// Checks if the given file contents matches the source file for the UPS patch
-    pub fn file_is_source(&self, content: &Vec<u8>) -> bool {
-        let file_crc32 = crc32::calculate(&content);
-        return file_crc32 == self.source_crc32;
+    /// Calculates the crc32 of the first `length` bytes of `file`
+    fn file_crc32(file: &mut File, length: u64) -> Result<u32, UpsError> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = Read::take(&mut *file, length);
+        let mut crc32 = Crc32::new();
+        let mut buffer = vec![0u8; UpsPatch::STREAM_BUFFER_SIZE];
+        loop {
+            let read = UpsPatch::read_chunk(&mut reader, &mut buffer)?;
+            crc32.update(&buffer[..read]);
+            if read < buffer.len() {
+                return Ok(crc32.finish());
+            }
+        }
     }
 
-    /// Checks if the given file contents matches the target file for the UPS patch
-    pub fn file_is_target(&self, content : &Vec<u8>) -> bool {
-        let file_crc32 = crc32::calculate(&content);
-        return file_crc32 == self.target_crc32;
+    /// XORs every hunk into `file`, leaving out the bytes past `size`
+    fn xor_into_file(&self, file: &mut File, size: u64) -> Result<(), UpsError> {
+        let mut buffer: Vec<u8> = vec![];
+        for (offset, xor_bytes) in &self.changes {
+            if *offset >= size {
+                continue;
+            }
+            buffer.resize(min(xor_bytes.len() as u64, size - offset) as usize, 0);
+            file.seek(SeekFrom::Start(*offset))?;
+            file.read_exact(&mut buffer)?;
+            for (byte, xor_byte) in buffer.iter_mut().zip(xor_bytes) {
+                *byte ^= xor_byte;
+            }
+            file.seek(SeekFrom::Start(*offset))?;
+            file.write_all(&buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the patch file into `writer`
+    ///
+    /// This is the only place where patches get serialised. The hunks are written in their
+    /// canonical form: sorted, with overlapping or touching hunks merged and split at their 0
+    /// bytes, so any list of `changes` can be written and loading the result gives back an
+    /// equivalent patch. The patch crc32 is calculated from the written bytes instead of taken
+    /// from [`patch_crc32`](UpsPatch::patch_crc32), so it is valid even if the patch was
+    /// modified after being created or loaded.
+    /// # Arguments
+    /// * `writer` - Where the patch gets written
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # use std::fs::File;
+    /// # use std::io::BufWriter;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// let mut patch_file = BufWriter::new(File::create("path/to/patch/file").unwrap());
+    /// patch.to_writer(&mut patch_file).unwrap();
+    /// ```
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<(), UpsError> {
+        let mut ups_writer = UpsWriter::new(writer, self.source_file_size, self.target_file_size)?;
+        for (offset, xor_bytes) in self.canonical_changes() {
+            ups_writer.write_hunk(offset, &xor_bytes)?;
+        }
+        ups_writer.finish(self.source_crc32, self.target_crc32)?;
+        Ok(())
     }
 
-    fn find_pointer(buff: &Vec<u8>, start: usize) -> (usize, u64) {
-        let (i, encoded_pointer) = UpsPatch::find_encoded_value(&buff, start);
-        let decoded_pointer = UpsPatch::decode(encoded_pointer);
-        return (i, decoded_pointer);
+    /// Returns a vector with the contents of the patch.ups file, as written by
+    /// [`to_writer`](UpsPatch::to_writer)
+    pub fn get_patch_file_contents(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+        // Writing into a vector can't fail, and the canonical hunks are always valid
+        self.to_writer(&mut output).expect("Canonical hunks can always be written");
+        output
     }
-    fn find_encoded_value(buff: &Vec<u8>, start: usize) -> (usize, Vec<u8>) {
-        let mut start = start;
-        while buff[start] == 0 {
-            start +=1;
+
+    /// Returns the changes in the form they can be stored in a patch file, which only differs from
+    /// `changes` if they were built by hand
+    fn canonical_changes(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut sorted: Vec<&(u64, Vec<u8>)> = self.changes.iter().collect();
+        sorted.sort_by_key(|(offset, _)| *offset);
+
+        // Overlapping and touching hunks get merged, XORing the bytes they share, which is what
+        // applying them one after the other does
+        let mut merged: Vec<(u64, Vec<u8>)> = vec![];
+        for (offset, xor_bytes) in sorted {
+            // Every hunk is followed by an unchanged byte, so nothing can change the last
+            // possible position, which no file reaches anyway
+            let length = min(xor_bytes.len() as u64, (u64::MAX - 1).saturating_sub(*offset)) as usize;
+            match merged.last_mut() {
+                Some((last_offset, last_bytes)) if *offset <= *last_offset + last_bytes.len() as u64 => {
+                    let start = (offset - *last_offset) as usize;
+                    if last_bytes.len() < start + length {
+                        last_bytes.resize(start + length, 0);
+                    }
+                    for (byte, xor_byte) in last_bytes[start..].iter_mut().zip(&xor_bytes[..length]) {
+                        *byte ^= xor_byte;
+                    }
+                }
+                _ => merged.push((*offset, xor_bytes[..length].to_vec())),
+            }
         }
-        let mut i = start;
-        while buff[i] & 0x80 == 0 {
-            i += 1;
+
+        // A 0 byte leaves the file unchanged and would end the hunk, so hunks are split around them
+        let mut changes: Vec<(u64, Vec<u8>)> = vec![];
+        for (offset, xor_bytes) in merged {
+            let mut start: u64 = 0;
+            for part in xor_bytes.split(|byte| *byte == 0) {
+                if !part.is_empty() {
+                    changes.push((offset + start, part.to_vec()));
+                }
+                start += part.len() as u64 + 1;
+            }
         }
-        i += 1;
-        return (i, buff[start..i].to_owned());
+        changes
     }
 
-    fn decode(input: Vec<u8>) -> u64 {
-        let mut value: u64 = 0;
-        let mut shift: u32 = 1;
-        let mut i: usize = 0;
-        let mut x: u8 = input[i];
-        i += 1;
-        value += ((x & 0x7f) as u32 * shift) as u64;
-        while x & 0x80 == 0 {
-            shift <<= 7;
-            value += shift as u64;
-            x = input[i];
-            i += 1;
-            value += ((x & 0x7F) as u32 * shift) as u64;
-        }
+    /// Checks if the file read from `reader` is the source file of the patch, reading it in
+    /// pieces instead of having it all in memory.
+    ///
+    /// Both the size and the crc32 of the file are compared. A file that turns out to be the
+    /// target file is reported as such, and any other file is reported as a size mismatch if
+    /// its size differs from the source file, or a crc32 mismatch otherwise.
+    /// # Arguments
+    /// * `reader` - The file to check, read until its end
+    /// # Examples
+    /// ```no_run
+    /// # use ups::{FileVerdict, UpsPatch};
+    /// # use std::fs::File;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// match patch.check_source_reader(File::open("path/to/rom").unwrap()).unwrap() {
+    ///     FileVerdict::Source => println!("Ready to patch"),
+    ///     FileVerdict::Target => println!("Already patched"),
+    ///     FileVerdict::SizeMismatch { .. } => println!("Wrong file"),
+    ///     FileVerdict::Crc32Mismatch { .. } => println!("Wrong version of the file"),
+    /// }
+    /// ```
+    pub fn check_source_reader<R: Read>(&self, reader: R) -> Result<FileVerdict, UpsError> {
+        let (size, crc32) = UpsPatch::measure(reader)?;
+        Ok(self.verdict(size, crc32, Direction::Forward))
+    }
 
-        return value;
+    /// Checks if the file read from `reader` is the target file of the patch, same as
+    /// [`check_source_reader`](UpsPatch::check_source_reader) does for the source file
+    /// # Arguments
+    /// * `reader` - The file to check, read until its end
+    pub fn check_target_reader<R: Read>(&self, reader: R) -> Result<FileVerdict, UpsError> {
+        let (size, crc32) = UpsPatch::measure(reader)?;
+        Ok(self.verdict(size, crc32, Direction::Reverse))
     }
 
+    /// Checks if the file at `path` is the source file of the patch, see
+    /// [`check_source_reader`](UpsPatch::check_source_reader)
+    pub fn check_source_file<P: AsRef<Path>>(&self, path: P) -> Result<FileVerdict, UpsError> {
+        self.check_source_reader(File::open(path)?)
+    }
 
-    fn encode(input: u64) -> Vec<u8> {
-        let mut input = input;
-        let mut bytes: Vec<u8> = vec![];
+    /// Checks if the file at `path` is the target file of the patch, see
+    /// [`check_target_reader`](UpsPatch::check_target_reader)
+    pub fn check_target_file<P: AsRef<Path>>(&self, path: P) -> Result<FileVerdict, UpsError> {
+        self.check_target_reader(File::open(path)?)
+    }
 
-        let mut x = input & 0x7f;
-        input >>= 7;
-        while input != 0
-        {
+    /// Returns the size and crc32 of everything in `reader`
+    fn measure<R: Read>(reader: R) -> Result<(u64, u32), UpsError> {
+        let mut reader = reader;
+        let mut size: u64 = 0;
+        let mut crc32 = Crc32::new();
+        let mut buffer = vec![0u8; UpsPatch::STREAM_BUFFER_SIZE];
+        loop {
+            let read = UpsPatch::read_chunk(&mut reader, &mut buffer)?;
+            crc32.update(&buffer[..read]);
+            size += read as u64;
+            if read < buffer.len() {
+                return Ok((size, crc32.finish()));
+            }
+        }
+    }
 
-            bytes.push(x as u8);
-            input -= 1;
-            x = input & 0x7f;
-            input >>= 7;
+    /// Classifies a file by its size and crc32, comparing it first with the file `direction`
+    /// starts from, and then with the other one
+    pub(crate) fn verdict(&self, size: u64, crc32: u32, direction: Direction) -> FileVerdict {
+        let is_source = size == self.source_file_size && crc32 == self.source_crc32;
+        let is_target = size == self.target_file_size && crc32 == self.target_crc32;
+        let (expected_size, expected_crc32) = match direction {
+            Direction::Forward => (self.source_file_size, self.source_crc32),
+            Direction::Reverse => (self.target_file_size, self.target_crc32),
+        };
+        match direction {
+            Direction::Forward if is_source => FileVerdict::Source,
+            Direction::Reverse if is_target => FileVerdict::Target,
+            _ if is_source => FileVerdict::Source,
+            _ if is_target => FileVerdict::Target,
+            _ if size != expected_size => FileVerdict::SizeMismatch { expected: expected_size, actual: size },
+            _ => FileVerdict::Crc32Mismatch { expected: expected_crc32, actual: crc32 },
         }
-        bytes.push((0x80 | x) as u8);
+    }
 
-        return bytes;
+    /// Checks if the given file contents matches the source file for the UPS patch, both in size
+    /// and crc32
+    pub fn file_is_source(&self, content: &[u8]) -> bool {
+        content.len() as u64 == self.source_file_size && crc32::calculate(content) == self.source_crc32
     }
 
+    /// Checks if the given file contents matches the target file for the UPS patch, both in size
+    /// and crc32
+    pub fn file_is_target(&self, content : &[u8]) -> bool {
+        content.len() as u64 == self.target_file_size && crc32::calculate(content) == self.target_crc32
+    }
 
 }
 #[cfg(test)]
 mod internal_tests {
-    use crate::{
-        crc32,
-        UpsPatch
-    };
-
+    use crate::crc32;
 
-    #[test]
-    fn can_decode(){
-        assert_eq!(UpsPatch::decode(vec![0x0,0x7f,0x7e,0x86]), 16777216);
-        assert_eq!(UpsPatch::decode(vec![0x9A]), 26)
-    }
-
-    #[test]
-    fn can_encode(){
-        assert_eq!(UpsPatch::encode(16777216), vec![0x0,0x7f,0x7e,0x86]);
-        assert_eq!(UpsPatch::encode(26), vec![0x9A]);
-    }
 
     #[test]
     fn can_perform_crc32_checksum(){
         let content = vec![0x11, 0x22, 0x33, 0x44];
-        assert_eq!(crc32::calculate(&*content), 0x77F29DD1 )
+        assert_eq!(crc32::calculate(&content), 0x77F29DD1 )
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use std::io::{Read, Seek, Write};
+
+use crate::{
+    crc32::Crc32,
+    varint,
+    UpsError,
+    UpsPatch,
+    LoadError::*,
+    ApplyError::*,
+};
+
+/// Size of the source crc32, target crc32 and patch crc32 at the end of the file
+const TRAILER_SIZE: usize = 12;
+/// Amount of bytes requested from the underlying reader at once
+const READ_SIZE: usize = 8 * 1024;
+
+/// Reads a UPS patch from any reader, decoding its hunks one at a time as they are requested
+/// instead of loading the whole patch in memory like [`UpsPatch::load`] does.
+///
+/// The header is read when the reader is created, then the hunks are obtained iterating over it.
+/// Each hunk is a tuple of the offset where it starts and the XOR bytes to apply there, same as
+/// the entries in [`UpsPatch::changes`].
+/// The checksums stored at the end of the patch are only available after all the hunks have been
+/// read, at which point the crc32 of the patch itself is verified, yielding an error if it
+/// doesn't match.
+/// # Examples
+/// ```no_run
+/// # use ups::UpsReader;
+/// # use std::fs::File;
+/// # use std::io::BufReader;
+/// let patch_file = BufReader::new(File::open("path/to/patch/file").unwrap());
+/// let mut reader = UpsReader::new(patch_file).unwrap();
+/// println!("{} -> {} bytes", reader.source_file_size(), reader.target_file_size());
+/// for hunk in &mut reader {
+///     let (offset, xor_bytes) = hunk.unwrap();
+///     println!("{} bytes changed at {}", xor_bytes.len(), offset);
+/// }
+/// println!("target crc32: {:08x}", reader.target_crc32().unwrap());
+/// ```
+pub struct UpsReader<R: Read> {
+    reader: R,
+    /// Bytes read from `reader` but not decoded yet
+    pending: VecDeque<u8>,
+    reader_finished: bool,
+    /// crc32 of all the decoded bytes
+    crc32: Crc32,
+    /// Amount of bytes decoded so far, used to report where errors happen
+    position: u64,
+    source_file_size: u64,
+    target_file_size: u64,
+    /// Position where the offset of the next hunk is relative to
+    next_hunk_base: u64,
+    /// source, target and patch crc32, once the end of the patch has been reached
+    checksums: Option<(u32, u32, u32)>,
+    finished: bool,
+}
+
+impl<R: Read> UpsReader<R> {
+    /// Creates the reader and reads the header of the patch, returns an error if `reader`
+    /// doesn't contain a UPS patch
+    /// # Arguments
+    /// * `reader` - Where the patch gets read from
+    pub fn new(reader: R) -> Result<UpsReader<R>, UpsError> {
+        let mut ups_reader = UpsReader {
+            reader,
+            pending: VecDeque::new(),
+            reader_finished: false,
+            crc32: Crc32::new(),
+            position: 0,
+            source_file_size: 0,
+            target_file_size: 0,
+            next_hunk_base: 0,
+            checksums: None,
+            finished: false,
+        };
+        // The magic is checked before holding back the checksums, so that short files that aren't
+        // patches aren't reported as truncated ones, same as UpsPatch::load does
+        let magic_length = UpsPatch::CANON_HEADER.len();
+        ups_reader.fill(magic_length)?;
+        if !ups_reader.pending.iter().take(magic_length).eq(&UpsPatch::CANON_HEADER) {
+            return Err(UpsError::Load(IsNotUpsFile));
+        }
+        for _ in 0..magic_length {
+            ups_reader.next_body_byte()?.ok_or(UpsError::Load(Truncated { offset: 0 }))?;
+        }
+        ups_reader.source_file_size = ups_reader.read_pointer()?;
+        ups_reader.target_file_size = ups_reader.read_pointer()?;
+        Ok(ups_reader)
+    }
+
+    /// The file size of the original file
+    pub fn source_file_size(&self) -> u64 {
+        self.source_file_size
+    }
+
+    /// The file size of the final file
+    pub fn target_file_size(&self) -> u64 {
+        self.target_file_size
+    }
+
+    /// The crc32 checksum of the source file, only known after all hunks have been read
+    pub fn source_crc32(&self) -> Option<u32> {
+        self.checksums.map(|(source_crc32, _, _)| source_crc32)
+    }
+
+    /// The crc32 checksum of the final file, only known after all hunks have been read
+    pub fn target_crc32(&self) -> Option<u32> {
+        self.checksums.map(|(_, target_crc32, _)| target_crc32)
+    }
+
+    /// The crc32 checksum of the patch file itself, only known after all hunks have been read
+    pub fn patch_crc32(&self) -> Option<u32> {
+        self.checksums.map(|(_, _, patch_crc32)| patch_crc32)
+    }
+
+    /// Applies the patch while it is being read, reading the source file from `source` and
+    /// writing the result into `target`.
+    ///
+    /// Works like [`UpsPatch::apply_stream`], so the size of the source is checked first but
+    /// mismatches of the checksums are only reported after the whole target has been written.
+    /// # Arguments
+    /// * `source` - The source file, it is read from the beginning regardless of its current position
+    /// * `target` - Where the patched file gets written
+    pub fn apply_stream<S: Read + Seek, W: Write>(mut self, source: &mut S, target: &mut W) -> Result<(), UpsError> {
+        UpsPatch::check_stream_size(source, self.source_file_size)?;
+        let target_file_size = self.target_file_size;
+        let (source_crc32, target_crc32) = UpsPatch::stream_hunks(&mut self, target_file_size, source, target)?;
+
+        // stream_hunks consumes every hunk, so the checksums have been read by now
+        let (expected_source_crc32, expected_target_crc32, _) = self.checksums.ok_or(UpsError::Load(crate::LoadError::Unknown))?;
+        if source_crc32 != expected_source_crc32 {
+            return Err(UpsError::Apply(SourceMismatch { expected: expected_source_crc32, actual: source_crc32 }));
+        }
+        if target_crc32 != expected_target_crc32 {
+            return Err(UpsError::Apply(TargetMismatch { expected: expected_target_crc32, actual: target_crc32 }));
+        }
+        Ok(())
+    }
+
+    /// Makes sure there are at least `amount` bytes pending, unless the reader has ended
+    fn fill(&mut self, amount: usize) -> Result<(), UpsError> {
+        let mut chunk = [0u8; READ_SIZE];
+        while self.pending.len() < amount && !self.reader_finished {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.reader_finished = true,
+                Ok(read) => self.pending.extend(&chunk[..read]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(UpsError::Io(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the next byte before the checksums at the end of the file, or `None` if only the
+    /// checksums are left
+    fn next_body_byte(&mut self) -> Result<Option<u8>, UpsError> {
+        self.fill(TRAILER_SIZE + 1)?;
+        if self.pending.len() <= TRAILER_SIZE {
+            return Ok(None);
+        }
+        let byte = self.pending.pop_front().unwrap();
+        self.crc32.update(&[byte]);
+        self.position += 1;
+        Ok(Some(byte))
+    }
+
+    fn read_pointer(&mut self) -> Result<u64, UpsError> {
+        let start = self.position;
+        varint::decode_from(|| self.next_body_byte(), start)
+    }
+
+    fn read_hunk(&mut self) -> Result<(u64, Vec<u8>), UpsError> {
+        let hunk_position = self.position;
+        let relative_offset = self.read_pointer()?;
+        let offset = self.next_hunk_base.checked_add(relative_offset)
+            .ok_or(UpsError::Load(VarintOverflow { offset: hunk_position }))?;
+        let mut xor_bytes: Vec<u8> = vec![];
+        loop {
+            match self.next_body_byte()? {
+                Some(0) => break,
+                Some(byte) => xor_bytes.push(byte),
+                None => return Err(UpsError::Load(UnterminatedHunk { offset: hunk_position })),
+            }
+        }
+        self.next_hunk_base = offset.checked_add(xor_bytes.len() as u64 + 1)
+            .ok_or(UpsError::Load(VarintOverflow { offset: hunk_position }))?;
+        Ok((offset, xor_bytes))
+    }
+
+    /// Reads the checksums at the end of the file and verifies the patch crc32
+    fn read_checksums(&mut self) -> Result<(), UpsError> {
+        if self.pending.len() != TRAILER_SIZE {
+            return Err(UpsError::Load(Truncated { offset: self.position }));
+        }
+        let mut trailer = [0u8; TRAILER_SIZE];
+        for byte in trailer.iter_mut() {
+            *byte = self.pending.pop_front().unwrap();
+        }
+        self.crc32.update(&trailer[0..8]);
+        let source_crc32 = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let target_crc32 = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        let patch_crc32 = u32::from_le_bytes([trailer[8], trailer[9], trailer[10], trailer[11]]);
+        let actual_patch_crc32 = self.crc32.finish();
+        if actual_patch_crc32 != patch_crc32 {
+            return Err(UpsError::Load(IsCorrupted { expected: patch_crc32, actual: actual_patch_crc32 }));
+        }
+        self.checksums = Some((source_crc32, target_crc32, patch_crc32));
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for UpsReader<R> {
+    type Item = Result<(u64, Vec<u8>), UpsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.fill(TRAILER_SIZE + 1).and_then(|_| {
+            if self.pending.len() > TRAILER_SIZE {
+                self.read_hunk().map(Some)
+            } else {
+                self.read_checksums().map(|_| None)
+            }
+        });
+        match result {
+            Ok(Some(hunk)) => Some(Ok(hunk)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::fmt::{Formatter, Write};
+use std::ops::Range;
+
+use crate::UpsPatch;
+
+/// Amount of bytes shown in each line of [`UpsPatch::dump`]
+const DUMP_LINE_SIZE: usize = 16;
+
+/// Statistics about the contents of a UPS patch, returned by [`UpsPatch::summary`]
+///
+/// Its `Display` implementation shows them one per line, except for the range of each hunk.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpsSummary {
+    /// The file size of the original file
+    pub source_file_size: u64,
+    /// The file size of the final file
+    pub target_file_size: u64,
+    /// How much bigger the final file is than the original one, negative if it is smaller
+    pub size_delta: i64,
+    /// The amount of hunks in the patch
+    pub hunk_count: usize,
+    /// The amount of bytes changed by all the hunks together
+    pub changed_bytes: u64,
+    /// The range of bytes changed by the longest hunk, the first one if several are as long,
+    /// or `None` if the patch has no hunks
+    pub largest_hunk: Option<Range<u64>>,
+    /// The range of bytes changed by each hunk, in the order they are in the patch
+    pub ranges: Vec<Range<u64>>,
+    /// The crc32 checksum of the source file
+    pub source_crc32: u32,
+    /// The crc32 checksum of the target file
+    pub target_crc32: u32,
+    /// The crc32 checksum of the patch file itself
+    pub patch_crc32: u32,
+}
+
+impl fmt::Display for UpsSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Source size:   {} bytes", self.source_file_size)?;
+        writeln!(f, "Target size:   {} bytes ({:+})", self.target_file_size, self.size_delta)?;
+        writeln!(f, "Source crc32:  {:08x}", self.source_crc32)?;
+        writeln!(f, "Target crc32:  {:08x}", self.target_crc32)?;
+        writeln!(f, "Patch crc32:   {:08x}", self.patch_crc32)?;
+        writeln!(f, "Hunks:         {}", self.hunk_count)?;
+        write!(f, "Changed bytes: {}", self.changed_bytes)?;
+        if let Some(largest_hunk) = &self.largest_hunk {
+            write!(f, "\nLargest hunk:  {} bytes at 0x{:x}", largest_hunk.end - largest_hunk.start, largest_hunk.start)?;
+        }
+        Ok(())
+    }
+}
+
+impl UpsPatch {
+    /// Returns statistics about the contents of the patch, to see what it changes without
+    /// applying it
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// let summary = patch.summary();
+    /// println!("{} bytes changed in {} hunks", summary.changed_bytes, summary.hunk_count);
+    /// ```
+    pub fn summary(&self) -> UpsSummary {
+        let ranges: Vec<Range<u64>> = self.changes.iter()
+            .map(|(offset, xor_bytes)| *offset..offset.saturating_add(xor_bytes.len() as u64))
+            .collect();
+        // max_by_key returns the last of the longest ones, so the search goes backwards
+        let largest_hunk = ranges.iter().rev().max_by_key(|range| range.end - range.start).cloned();
+        UpsSummary {
+            source_file_size: self.source_file_size,
+            target_file_size: self.target_file_size,
+            // Wrapping keeps the right value for any pair of sizes less than 2^63 bytes apart
+            size_delta: self.target_file_size.wrapping_sub(self.source_file_size) as i64,
+            hunk_count: self.changes.len(),
+            changed_bytes: ranges.iter().map(|range| range.end - range.start).sum(),
+            largest_hunk,
+            ranges,
+            source_crc32: self.source_crc32,
+            target_crc32: self.target_crc32,
+            patch_crc32: self.patch_crc32,
+        }
+    }
+
+    /// Renders every hunk of the patch as its offset followed by its XOR bytes in hex, 16 bytes
+    /// per line.
+    ///
+    /// When the content of the source file is given, the bytes of the source file and the
+    /// resulting target file at each hunk are shown under its XOR bytes too, with `--` for the
+    /// positions that are past the end of either file.
+    /// # Arguments
+    /// * `source` - The content of the source file, if the actual bytes have to be shown
+    /// # Examples
+    /// ```no_run
+    /// # use ups::UpsPatch;
+    /// # let patch = UpsPatch::load(&vec![]).unwrap();
+    /// # let source_file_content: Vec<u8> = vec![];
+    /// print!("{}", patch.dump(Some(&source_file_content)));
+    /// ```
+    pub fn dump(&self, source: Option<&[u8]>) -> String {
+        let mut output = String::new();
+        self.write_dump(&mut output, source).expect("Writing into a String can't fail");
+        output
+    }
+
+    fn write_dump(&self, output: &mut String, source: Option<&[u8]>) -> fmt::Result {
+        for (i, (offset, xor_bytes)) in self.changes.iter().enumerate() {
+            writeln!(output, "Hunk {} at 0x{:08x}, {} bytes", i, offset, xor_bytes.len())?;
+            for (line, line_xor_bytes) in xor_bytes.chunks(DUMP_LINE_SIZE).enumerate() {
+                UpsPatch::write_dump_line(output, "xor", line_xor_bytes.iter().map(|byte| Some(*byte)))?;
+                if let Some(source) = source {
+                    let line_offset = offset.saturating_add((line * DUMP_LINE_SIZE) as u64);
+                    let source_byte = |j: usize| {
+                        let position = line_offset.saturating_add(j as u64);
+                        if position < source.len() as u64 { Some(source[position as usize]) } else { None }
+                    };
+                    let target_byte = |j: usize| {
+                        let position = line_offset.saturating_add(j as u64);
+                        if position < self.target_file_size {
+                            Some(source_byte(j).unwrap_or(0) ^ line_xor_bytes[j])
+                        } else {
+                            None
+                        }
+                    };
+                    UpsPatch::write_dump_line(output, "source", (0..line_xor_bytes.len()).map(source_byte))?;
+                    UpsPatch::write_dump_line(output, "target", (0..line_xor_bytes.len()).map(target_byte))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_dump_line<I: Iterator<Item = Option<u8>>>(output: &mut String, label: &str, bytes: I) -> fmt::Result {
+        write!(output, "  {:<6}", label)?;
+        for byte in bytes {
+            match byte {
+                Some(byte) => write!(output, " {:02x}", byte)?,
+                None => output.push_str(" --"),
+            }
+        }
+        writeln!(output)
+    }
+}
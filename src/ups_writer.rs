@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use crate::{
+    crc32::Crc32,
+    varint,
+    UpsError,
+    UpsPatch,
+    CreateError::*,
+};
+
+/// Writes a UPS patch into any writer one hunk at a time, without having to keep the whole patch
+/// in memory.
+///
+/// The header is written when the writer is created, then each call to
+/// [`write_hunk`](UpsWriter::write_hunk) writes one hunk, and [`finish`](UpsWriter::finish)
+/// appends the checksums that close the patch.
+/// # Examples
+/// ```no_run
+/// # use ups::UpsWriter;
+/// # use std::fs::File;
+/// # use std::io::BufWriter;
+/// let patch_file = BufWriter::new(File::create("path/to/patch/file").unwrap());
+/// let mut writer = UpsWriter::new(patch_file, 28, 27).unwrap();
+/// writer.write_hunk(17, &[0x15, 0x06, 0x1B]).unwrap();
+/// writer.finish(0x29E0B36E, 0x23a777e3).unwrap();
+/// ```
+pub struct UpsWriter<W: Write> {
+    writer: W,
+    /// crc32 of all the bytes written so far
+    crc32: Crc32,
+    /// Position where the offset of the next hunk is relative to
+    next_hunk_base: u64,
+}
+
+impl<W: Write> UpsWriter<W> {
+    /// Creates the writer and writes the header of the patch
+    /// # Arguments
+    /// * `writer` - Where the patch gets written
+    /// * `source_file_size` - The file size of the original file
+    /// * `target_file_size` - The file size of the final file
+    pub fn new(writer: W, source_file_size: u64, target_file_size: u64) -> Result<UpsWriter<W>, UpsError> {
+        let mut ups_writer = UpsWriter {
+            writer,
+            crc32: Crc32::new(),
+            next_hunk_base: 0,
+        };
+        ups_writer.write_bytes(&UpsPatch::CANON_HEADER)?;
+        ups_writer.write_bytes(&varint::encode(source_file_size))?;
+        ups_writer.write_bytes(&varint::encode(target_file_size))?;
+        Ok(ups_writer)
+    }
+
+    /// Writes the next hunk of the patch
+    ///
+    /// Hunks have to be written in order, and each one must start at least one byte after the
+    /// end of the previous one, as the byte that follows a hunk is always left unchanged.
+    /// # Arguments
+    /// * `offset` - The position of the file where the hunk starts
+    /// * `xor_bytes` - The XOR bytes to apply from that position, none of them can be 0
+    pub fn write_hunk(&mut self, offset: u64, xor_bytes: &[u8]) -> Result<(), UpsError> {
+        let next_hunk_base = offset.checked_add(xor_bytes.len() as u64 + 1);
+        if offset < self.next_hunk_base || next_hunk_base.is_none() || xor_bytes.contains(&0) {
+            return Err(UpsError::Create(InvalidHunk { offset }));
+        }
+        self.write_bytes(&varint::encode(offset - self.next_hunk_base))?;
+        self.write_bytes(xor_bytes)?;
+        self.write_bytes(&[0x00])?;
+        self.next_hunk_base = next_hunk_base.unwrap();
+        Ok(())
+    }
+
+    /// Writes the checksums at the end of the patch and returns the underlying writer
+    /// # Arguments
+    /// * `source_crc32` - The crc32 checksum of the source file
+    /// * `target_crc32` - The crc32 checksum of the final file
+    pub fn finish(mut self, source_crc32: u32, target_crc32: u32) -> Result<W, UpsError> {
+        self.write_bytes(&source_crc32.to_le_bytes())?;
+        self.write_bytes(&target_crc32.to_le_bytes())?;
+        let patch_crc32 = self.crc32.finish();
+        self.writer.write_all(&patch_crc32.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), UpsError> {
+        self.writer.write_all(bytes)?;
+        self.crc32.update(bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Variable length encoding used for the numbers stored in UPS and BPS patches
+//!
+//! Each byte holds 7 bits of the number, starting from the lowest ones, and the last byte has its
+//! highest bit set. Every byte after the first one also adds one to the number, so that each
+//! number has a single possible encoding.
+
+use crate::{
+    LoadError,
+    LoadError::*,
+};
+
+/// Encodes a number into its variable length representation
+pub(crate) fn encode(input: u64) -> Vec<u8> {
+    let mut input = input;
+    let mut bytes: Vec<u8> = vec![];
+
+    let mut x = input & 0x7f;
+    input >>= 7;
+    while input != 0
+    {
+
+        bytes.push(x as u8);
+        input -= 1;
+        x = input & 0x7f;
+        input >>= 7;
+    }
+    bytes.push((0x80 | x) as u8);
+
+    bytes
+}
+
+/// Decodes the number that starts at `start`, returning it along with the position right after
+/// it, or an error if `buff` ends before the number does or the number doesn't fit in a u64
+pub(crate) fn decode(buff: &[u8], start: usize) -> Result<(u64, usize), LoadError> {
+    let mut i = start;
+    let value = decode_from(|| {
+        let byte = buff.get(i).copied();
+        i += 1;
+        Ok::<_, LoadError>(byte)
+    }, start as u64)?;
+    Ok((value, i))
+}
+
+/// Decodes a number reading its bytes one by one from `next_byte`, which returns `None` once there
+/// are no more bytes. `start` is the offset of the number, used for the errors
+pub(crate) fn decode_from<E: From<LoadError>>(mut next_byte: impl FnMut() -> Result<Option<u8>, E>, start: u64) -> Result<u64, E> {
+    let overflow = || E::from(VarintOverflow { offset: start });
+    let mut value: u64 = 0;
+    let mut shift: u64 = 1;
+    loop {
+        let x = next_byte()?.ok_or_else(|| E::from(UnterminatedVarint { offset: start }))?;
+        value = (x as u64 & 0x7f).checked_mul(shift)
+            .and_then(|added| value.checked_add(added))
+            .ok_or_else(overflow)?;
+        if x & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+        value = value.checked_add(shift).ok_or_else(overflow)?;
+    }
+}
+
+#[cfg(test)]
+mod internal_tests {
+    use crate::{
+        varint,
+        LoadError,
+        LoadError::*,
+        UpsError,
+    };
+
+    #[test]
+    fn can_encode(){
+        assert_eq!(varint::encode(16777216), vec![0x0,0x7f,0x7e,0x86]);
+        assert_eq!(varint::encode(26), vec![0x9A]);
+    }
+
+    #[test]
+    fn can_decode(){
+        assert_eq!(varint::decode(&[0x0,0x7f,0x7e,0x86], 0), Ok((16777216, 4)));
+        assert_eq!(varint::decode(&[0x55, 0x9A, 0x00], 1), Ok((26, 2)));
+        assert_eq!(varint::decode(&[0x0,0x7f,0x7e], 0), Err(UnterminatedVarint { offset: 0 }));
+        assert_eq!(varint::decode(&[0x00; 11], 0), Err(VarintOverflow { offset: 0 }));
+    }
+
+    #[test]
+    fn can_decode_from_a_byte_source(){
+        let mut bytes = vec![0x0, 0x7f, 0x7e, 0x86, 0x9A].into_iter();
+        let mut next_byte = || Ok::<_, LoadError>(bytes.next());
+        assert_eq!(varint::decode_from(&mut next_byte, 3), Ok(16777216));
+        assert_eq!(varint::decode_from(&mut next_byte, 7), Ok(26));
+        assert_eq!(varint::decode_from(&mut next_byte, 8), Err(UnterminatedVarint { offset: 8 }));
+
+        let mut zeros = std::iter::repeat(0x00);
+        assert_eq!(varint::decode_from(|| Ok::<_, LoadError>(zeros.next()), 5), Err(VarintOverflow { offset: 5 }));
+
+        let io_error = || Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        assert_eq!(varint::decode_from::<UpsError>(io_error, 0), Err(UpsError::Io(std::io::ErrorKind::UnexpectedEof.into())));
+    }
+
+    #[test]
+    fn can_handle_the_whole_u64_range(){
+        let max_encoding = [0x7f, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x7e, 0x80];
+        assert_eq!(varint::encode(u64::MAX), max_encoding.to_vec());
+        assert_eq!(varint::decode(&max_encoding, 0), Ok((u64::MAX, 10)));
+
+        // One more than u64::MAX, and a last byte that makes it way bigger
+        let mut past_max = max_encoding;
+        past_max[0] = 0x00;
+        past_max[1] = 0x7f;
+        assert_eq!(varint::decode(&past_max, 0), Err(VarintOverflow { offset: 0 }));
+        past_max = max_encoding;
+        past_max[9] = 0x81;
+        assert_eq!(varint::decode(&past_max, 0), Err(VarintOverflow { offset: 0 }));
+    }
+
+    #[test]
+    fn encoding_length_grows_at_each_boundary(){
+        // The smallest number that needs n + 1 bytes is 0x80 + 0x80^2 + ... + 0x80^n
+        let mut boundary: u64 = 0;
+        let mut power: u64 = 1;
+        for length in 1..10 {
+            power *= 0x80;
+            boundary += power;
+            assert_eq!(varint::encode(boundary - 1).len(), length);
+            assert_eq!(varint::encode(boundary).len(), length + 1);
+            for value in [boundary - 1, boundary] {
+                assert_eq!(varint::decode(&varint::encode(value), 0).map(|(decoded, _)| decoded), Ok(value));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_random_numbers(){
+        let mut state: u64 = 0x9E3779B97F4A7C15;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            // Shift by a random amount so that every encoding length gets tested
+            let value = state >> (state % 64);
+            let mut encoded = vec![0x55];
+            encoded.extend(varint::encode(value));
+            encoded.push(0x00);
+            assert_eq!(varint::decode(&encoded, 1), Ok((value, encoded.len() - 1)));
+        }
+    }
+}
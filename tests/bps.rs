@@ -0,0 +1,117 @@
+use ups::{BpsAction, BpsPatch, UpsPatch};
+mod common;
+use common::*;
+
+#[test]
+fn can_apply_bps_actions() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let patch = BpsPatch {
+        source_file_size: 28,
+        target_file_size: 27,
+        metadata: vec![],
+        actions: vec![
+            BpsAction::SourceRead { length: 17 },
+            BpsAction::TargetRead { data: b"fin".to_vec() },
+            BpsAction::TargetCopy { length: 1, offset: 8 },
+            BpsAction::TargetRead { data: b"l".to_vec() },
+            BpsAction::SourceCopy { length: 5, offset: 23 },
+        ],
+        source_crc32: 0,
+        target_crc32: 0,
+        patch_crc32: 0,
+    };
+    assert_eq!(patch.apply_no_check(&source_content).unwrap(), load_file_content(TARGET_PATH_1));
+}
+
+#[test]
+fn can_save_zero_length_bps_actions() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let patch = BpsPatch {
+        source_file_size: 28,
+        target_file_size: 27,
+        metadata: vec![],
+        actions: vec![
+            BpsAction::SourceRead { length: 0 },
+            BpsAction::SourceRead { length: 17 },
+            BpsAction::TargetRead { data: vec![] },
+            BpsAction::TargetRead { data: b"fin".to_vec() },
+            BpsAction::TargetCopy { length: 0, offset: 3 },
+            BpsAction::TargetCopy { length: 1, offset: 5 },
+            BpsAction::TargetRead { data: b"l".to_vec() },
+            BpsAction::SourceCopy { length: 0, offset: 25 },
+            BpsAction::SourceCopy { length: 5, offset: -2 },
+        ],
+        source_crc32: 0,
+        target_crc32: 0,
+        patch_crc32: 0,
+    };
+    assert_eq!(patch.apply_no_check(&source_content).unwrap(), load_file_content(TARGET_PATH_1));
+
+    let loaded_patch = BpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+    assert_eq!(loaded_patch.actions.len(), 5);
+    assert_eq!(loaded_patch.apply_no_check(&source_content).unwrap(), load_file_content(TARGET_PATH_1));
+}
+
+#[test]
+fn can_apply_overlapping_target_copy() {
+    let patch = BpsPatch {
+        source_file_size: 0,
+        target_file_size: 9,
+        metadata: vec![],
+        actions: vec![
+            BpsAction::TargetRead { data: b"ab".to_vec() },
+            BpsAction::TargetCopy { length: 7, offset: 0 },
+        ],
+        source_crc32: 0,
+        target_crc32: 0,
+        patch_crc32: 0,
+    };
+    assert_eq!(patch.apply_no_check(&[]).unwrap(), b"abababab".iter().chain(b"a").copied().collect::<Vec<u8>>());
+}
+
+#[test]
+fn can_create_bps_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for target_path in &[TARGET_PATH_1, TARGET_PATH_2] {
+        let target_content = load_file_content(target_path);
+        let patch = BpsPatch::create(&source_content, &target_content, b"<test/>");
+        assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+
+        let loaded_patch = BpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+        assert_eq!(loaded_patch, patch);
+        assert_eq!(loaded_patch.metadata, b"<test/>".to_vec());
+    }
+}
+
+#[test]
+fn can_save_modified_bps_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let mut patch = BpsPatch::create(&source_content, &target_content, &[]);
+    patch.metadata = b"<edited/>".to_vec();
+
+    let loaded_patch = BpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+    assert_eq!(loaded_patch.metadata, b"<edited/>".to_vec());
+    assert_eq!(loaded_patch.apply(&source_content).unwrap(), target_content);
+}
+
+#[test]
+fn creates_small_patches_for_shifted_data() {
+    let mut state: u32 = 0x1234_5678;
+    let source_content: Vec<u8> = (0..100_000).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect();
+    let mut target_content = vec![0x42];
+    target_content.extend(&source_content[..50_000]);
+    target_content.extend(&source_content[60_000..]);
+    target_content.extend(&source_content[..1_000]);
+
+    let patch = BpsPatch::create(&source_content, &target_content, &[]);
+    assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+    let patch_content = patch.get_patch_file_contents();
+    assert!(patch_content.len() < 64);
+    assert!(UpsPatch::create(&source_content, &target_content).get_patch_file_contents().len() > 50_000);
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use ups::UpsPatch;
+mod common;
+use common::*;
+
+fn ups(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ups"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn can_apply_from_stdin_to_stdout() {
+    let output = ups(&["apply", "-", PATCH_PATH_2, "-"], &load_file_content(SOURCE_PATH));
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, load_file_content(TARGET_PATH_2));
+}
+
+#[test]
+fn can_revert_to_stdout() {
+    let output = ups(&["revert", TARGET_PATH_1, PATCH_PATH_1, "-"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, load_file_content(SOURCE_PATH));
+}
+
+#[test]
+fn can_create_patch_file() {
+    let patch_path = temp_path("patch2.ups");
+    let output = ups(&["create", SOURCE_PATH, TARGET_PATH_2, patch_path.to_str().unwrap()], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(&patch_path).unwrap(), load_file_content(PATCH_PATH_2));
+    fs::remove_file(&patch_path).unwrap();
+}
+
+#[test]
+fn can_invert_to_stdout() {
+    let output = ups(&["invert", PATCH_PATH_1, "-"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    let inverted_patch = UpsPatch::load(&output.stdout).unwrap();
+    assert_eq!(inverted_patch.apply(&load_file_content(TARGET_PATH_1)).unwrap(), load_file_content(SOURCE_PATH));
+}
+
+#[test]
+fn can_show_info_and_verify() {
+    let output = ups(&["info", PATCH_PATH_1], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("29e0b36e"));
+
+    let output = ups(&["info", PATCH_PATH_2, "--hunks", SOURCE_PATH], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("Hunk 2 at 0x0000001a, 8 bytes"));
+
+    assert_eq!(ups(&["verify", SOURCE_PATH, PATCH_PATH_1], &[]).status.code(), Some(0));
+    assert_eq!(ups(&["verify", TARGET_PATH_1, PATCH_PATH_1], &[]).status.code(), Some(0));
+}
+
+#[test]
+fn exits_with_error_codes() {
+    assert_eq!(ups(&["apply"], &[]).status.code(), Some(1));
+    assert_eq!(ups(&["info", "tests/does-not-exist.ups"], &[]).status.code(), Some(2));
+    assert_eq!(ups(&["info", SOURCE_PATH], &[]).status.code(), Some(3));
+    let output = ups(&["verify", TARGET_PATH_2, PATCH_PATH_1], &[]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("expected 28 bytes"));
+    let output = ups(&["verify", "-", PATCH_PATH_1], b"has the size of the source!!");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("expected 29e0b36e"));
+    assert_eq!(ups(&["apply", "-", PATCH_PATH_1, "-"], b"not the source").status.code(), Some(4));
+}
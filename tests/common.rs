@@ -1,5 +1,7 @@
+#![allow(dead_code)]
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 
 pub const SOURCE_PATH: &str = "tests/source.txt";
 pub const TARGET_PATH_1: &str = "tests/final1.txt";
@@ -11,6 +13,13 @@ pub const PATCH_PATH_2: &str = "tests/patch2.ups";
 pub fn load_file_content(path: &str) -> Vec<u8> {
     let mut patch_file = File::open(path).unwrap();
     let mut content : Vec<u8> = vec![];
-    patch_file.read_to_end(&mut content);
-    return content
-}
\ No newline at end of file
+    patch_file.read_to_end(&mut content).unwrap();
+    content
+}
+
+/// A path in the temporary directory, unique to the running test binary
+pub fn temp_path(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("ups-test-{}-{}", std::process::id(), name));
+    path
+}
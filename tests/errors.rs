@@ -1,7 +1,19 @@
 
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor};
 use ups::{
+    load_any,
+    ApplyOptions,
+    BpsAction,
+    BpsPatch,
+    FileVerdict,
+    IpsPatch,
+    SourceDiagnosis,
     UpsError,
     UpsPatch,
+    UpsReader,
+    UpsWriter,
     LoadError::*,
     CreateError::*,
     ApplyError::*,
@@ -18,10 +30,82 @@ fn throws_not_ups_file_error(){
 #[test]
 fn throws_corrupt_file_error(){
     let mut content = load_file_content(PATCH_PATH_1);
-    content[7] = content[7]+1;
+    content[7] += 1;
     let result = UpsPatch::load(&content);
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), UpsError::Load(IsCorrupted))
+    assert_eq!(result.unwrap_err(), UpsError::Load(IsCorrupted { expected: 0xffa6802b, actual: 0x500fcde1 }))
+}
+
+/// Appends the checksums to the given patch body, with a valid patch crc32 so that loading it
+/// gets past the checksum verification
+fn with_checksums(body: &[u8]) -> Vec<u8> {
+    let mut content = body.to_vec();
+    content.extend([0u8; 8]);
+    let mut crc32 = 0xFFFFFFFFu32;
+    for byte in &content {
+        crc32 ^= *byte as u32;
+        for _ in 0..8 {
+            crc32 = if crc32 & 1 != 0 { (crc32 >> 1) ^ 0xEDB88320 } else { crc32 >> 1 };
+        }
+    }
+    content.extend((!crc32).to_le_bytes());
+    content
+}
+
+#[test]
+fn throws_truncated_file_error(){
+    assert_eq!(UpsPatch::load(b""), Err(UpsError::Load(IsNotUpsFile)));
+    assert_eq!(UpsPatch::load(b"UPS"), Err(UpsError::Load(IsNotUpsFile)));
+    assert_eq!(UpsPatch::load(b"UPS1"), Err(UpsError::Load(Truncated { offset: 0 })));
+    assert_eq!(UpsPatch::load(b"UPS1\x9c\x9b\x00\x00\x00\x00\x00\x00\x00\x00\x00"), Err(UpsError::Load(Truncated { offset: 0 })));
+}
+
+#[test]
+fn throws_malformed_patch_errors(){
+    let unterminated_size = with_checksums(b"UPS1\x9c\x00");
+    assert_eq!(UpsPatch::load(&unterminated_size), Err(UpsError::Load(UnterminatedVarint { offset: 5 })));
+
+    let overflowing_size = with_checksums(b"UPS1\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+    assert_eq!(UpsPatch::load(&overflowing_size), Err(UpsError::Load(VarintOverflow { offset: 4 })));
+
+    let unterminated_hunk = with_checksums(b"UPS1\x9c\x9b\x84\x01\x02");
+    assert_eq!(UpsPatch::load(&unterminated_hunk), Err(UpsError::Load(UnterminatedHunk { offset: 6 })));
+
+    let overflowing_offset = with_checksums(b"UPS1\x9c\x9b\x7f\x7e\x7e\x7e\x7e\x7e\x7e\x7e\x7e\x80\x01\x00\x80\x01\x00");
+    assert_eq!(UpsPatch::load(&overflowing_offset), Err(UpsError::Load(VarintOverflow { offset: 6 })));
+}
+
+#[test]
+fn survives_fuzzed_patches(){
+    let patch_content = load_file_content(PATCH_PATH_2);
+    let source_content = load_file_content(SOURCE_PATH);
+    for length in 0..patch_content.len() {
+        assert!(UpsPatch::load(&patch_content[..length]).is_err());
+    }
+
+    let mut state: u64 = 0x2545F4914F6CDD1D;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..5000 {
+        let body_length = patch_content.len() - 12;
+        let mut body = patch_content[..body_length].to_vec();
+        for _ in 0..1 + next() % 4 {
+            let i = next() as usize % body.len();
+            body[i] = next() as u8;
+        }
+        body.truncate(body.len() - next() as usize % 4);
+        // Loading has to fail or succeed without panicking, and so does applying what it loads
+        if let Ok(patch) = UpsPatch::load(&with_checksums(&body)) {
+            if patch.source_file_size.max(patch.target_file_size) < 0x10000 {
+                let _ = patch.apply_no_check(&source_content);
+                let _ = patch.revert_no_check(&source_content);
+            }
+        }
+    }
 }
 
 #[test]
@@ -30,8 +114,303 @@ fn throws_wrong_source_error(){
     let patch = UpsPatch::load(&patch_content).unwrap();
     let mut source_content = load_file_content(SOURCE_PATH);
     assert!(patch.file_is_source(&source_content));
-    source_content[7] = source_content[7]+1;
+    source_content[7] += 1;
     let result = patch.apply(&source_content);
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), UpsError::Apply(SourceMismatch))
-}
\ No newline at end of file
+    assert_eq!(result.unwrap_err(), UpsError::Apply(SourceMismatch { expected: 0x29e0b36e, actual: 0xb63a30f0 }))
+}
+#[test]
+fn throws_wrong_source_error_when_streaming(){
+    let patch_content = load_file_content(PATCH_PATH_1);
+    let patch = UpsPatch::load(&patch_content).unwrap();
+    let mut source_content = load_file_content(SOURCE_PATH);
+    source_content[7] += 1;
+    let mut target: Vec<u8> = vec![];
+    let result = patch.apply_stream(&mut Cursor::new(source_content), &mut target);
+    assert!(result.is_err());
+    assert_eq!(result.unwrap_err(), UpsError::Apply(SourceMismatch { expected: 0x29e0b36e, actual: 0xb63a30f0 }))
+}
+
+#[test]
+fn stream_reader_throws_not_ups_file_error(){
+    let source_content = load_file_content(SOURCE_PATH);
+    let result = UpsReader::new(Cursor::new(source_content));
+    assert!(result.is_err());
+    assert_eq!(result.err().unwrap(), UpsError::Load(IsNotUpsFile));
+
+    // Files too short to hold the checksums of a patch
+    assert_eq!(UpsReader::new(Cursor::new(b"PATCHEOF")).err(), Some(UpsError::Load(IsNotUpsFile)));
+    assert_eq!(UpsReader::new(Cursor::new(b"UPS")).err(), Some(UpsError::Load(IsNotUpsFile)));
+    assert_eq!(UpsReader::new(Cursor::new(b"UPS1\x80\x80")).err(), Some(UpsError::Load(Truncated { offset: 0 })));
+}
+
+#[test]
+fn stream_reader_throws_corrupt_file_error(){
+    let mut content = load_file_content(PATCH_PATH_1);
+    content[7] += 1;
+    let reader = UpsReader::new(Cursor::new(content)).unwrap();
+    let result: Result<Vec<(u64, Vec<u8>)>, UpsError> = reader.collect();
+    assert!(result.is_err());
+    assert_eq!(result.unwrap_err(), UpsError::Load(IsCorrupted { expected: 0xffa6802b, actual: 0x500fcde1 }))
+}
+
+#[test]
+fn stream_reader_throws_malformed_patch_errors(){
+    let mut overflowing_size = b"UPS1".to_vec();
+    overflowing_size.extend([0x00; 11]);
+    overflowing_size.extend([0x00; 12]);
+    assert_eq!(UpsReader::new(Cursor::new(overflowing_size)).err(), Some(UpsError::Load(VarintOverflow { offset: 4 })));
+
+    let mut unterminated_hunk = b"UPS1\x9c\x9b\x84\x01\x02".to_vec();
+    unterminated_hunk.extend([0x00; 12]);
+    let mut reader = UpsReader::new(Cursor::new(unterminated_hunk)).unwrap();
+    assert_eq!(reader.next(), Some(Err(UpsError::Load(UnterminatedHunk { offset: 6 }))));
+
+    let truncated = b"UPS1\x9c\x9b\x00\x00".to_vec();
+    assert_eq!(UpsReader::new(Cursor::new(truncated)).err(), Some(UpsError::Load(Truncated { offset: 0 })));
+}
+
+#[test]
+fn stream_writer_throws_invalid_hunk_error(){
+    let mut writer = UpsWriter::new(vec![], 28, 27).unwrap();
+    writer.write_hunk(4, &[0x01, 0x02]).unwrap();
+    assert_eq!(writer.write_hunk(6, &[0x03]), Err(UpsError::Create(InvalidHunk { offset: 6 })));
+    assert_eq!(writer.write_hunk(10, &[0x03, 0x00, 0x04]), Err(UpsError::Create(InvalidHunk { offset: 10 })));
+    assert!(writer.write_hunk(7, &[0x03]).is_ok());
+}
+
+#[test]
+fn throws_too_big_error_for_huge_declared_sizes(){
+    let source_content = load_file_content(SOURCE_PATH);
+    let mut patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    patch.target_file_size = u64::MAX;
+    let too_big = UpsError::Apply(TooBig { size: u64::MAX });
+    assert_eq!(patch.apply(&source_content).unwrap_err(), too_big);
+    assert_eq!(patch.apply_no_check(&source_content).unwrap_err(), too_big);
+    assert_eq!(patch.revert_no_check(&[]).unwrap_err(), too_big);
+
+    // The same patch read from a file
+    let patch = UpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+    assert_eq!(patch.apply(&source_content).unwrap_err(), too_big);
+}
+
+#[test]
+fn diagnoses_sources_for_huge_declared_sizes(){
+    let source_content = load_file_content(SOURCE_PATH);
+    let mut patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    patch.source_file_size = 1 << 62;
+    let patch = UpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+    assert_eq!(patch.diagnose_source(&source_content), SourceDiagnosis::AsIs(FileVerdict::SizeMismatch {
+        expected: 1 << 62,
+        actual: source_content.len() as u64,
+    }));
+}
+
+#[test]
+fn throws_wrong_target_error_when_reverting(){
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let source_content = load_file_content(SOURCE_PATH);
+    assert_eq!(patch.direction_for(&source_content[1..]), None);
+    assert_eq!(patch.revert(&source_content), Err(UpsError::Apply(SourceSizeMismatch { expected: 27, actual: 28 })));
+    let mut target_content = load_file_content(TARGET_PATH_1);
+    target_content[7] ^= 0x01;
+    assert_eq!(patch.revert(&target_content), Err(UpsError::Apply(SourceMismatch { expected: 0x23a777e3, actual: 0x46c04ca5 })));
+}
+
+#[test]
+fn throws_source_size_mismatch_error(){
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let source_content = load_file_content(SOURCE_PATH);
+    let mut padded_content = source_content.clone();
+    padded_content.push(0x00);
+    let truncated = UpsError::Apply(SourceSizeMismatch { expected: 28, actual: 27 });
+    let padded = UpsError::Apply(SourceSizeMismatch { expected: 28, actual: 29 });
+    assert_eq!(patch.apply(&source_content[..27]).unwrap_err(), truncated);
+    assert_eq!(patch.apply(&padded_content).unwrap_err(), padded);
+    assert!(!patch.file_is_source(&padded_content));
+
+    let mut output: Vec<u8> = vec![];
+    assert_eq!(patch.apply_stream(&mut Cursor::new(&source_content[..27]), &mut output).unwrap_err(), truncated);
+    assert_eq!(patch.apply_stream(&mut Cursor::new(&padded_content), &mut output).unwrap_err(), padded);
+    let reader = UpsReader::new(Cursor::new(load_file_content(PATCH_PATH_1))).unwrap();
+    assert_eq!(reader.apply_stream(&mut Cursor::new(&padded_content), &mut output).unwrap_err(), padded);
+    assert!(output.is_empty());
+
+    let path = temp_path("in_place_padded_source.txt");
+    fs::write(&path, &padded_content).unwrap();
+    let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    assert_eq!(patch.apply_in_place(&mut file, false).unwrap_err(), padded);
+    drop(file);
+    assert_eq!(fs::read(&path).unwrap(), padded_content);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn strict_apply_throws_out_of_bounds_error(){
+    let source_content = load_file_content(SOURCE_PATH);
+    let mut patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let strict = ApplyOptions { strict: true, ..ApplyOptions::default() };
+    assert_eq!(patch.apply_with_options(&source_content, &strict), Ok(load_file_content(TARGET_PATH_1)));
+
+    // Both files are 28 bytes at most, so these hunks go past their end
+    patch.changes.push((26, vec![0x01, 0x02, 0x03]));
+    assert_eq!(patch.apply_with_options(&source_content, &strict), Err(UpsError::Apply(OutOfBounds { offset: 28 })));
+    patch.changes.pop();
+    patch.changes.push((40, vec![0x01]));
+    assert_eq!(patch.apply_with_options(&source_content, &strict), Err(UpsError::Apply(OutOfBounds { offset: 40 })));
+    assert_eq!(patch.apply(&source_content), Ok(load_file_content(TARGET_PATH_1)));
+}
+
+#[test]
+fn apply_in_place_leaves_wrong_source_untouched(){
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let mut source_content = load_file_content(SOURCE_PATH);
+    source_content[7] += 1;
+    let path = temp_path("in_place_wrong_source.txt");
+    fs::write(&path, &source_content).unwrap();
+    let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let result = patch.apply_in_place(&mut file, false);
+    assert_eq!(result, Err(UpsError::Apply(SourceMismatch { expected: 0x29e0b36e, actual: 0xb63a30f0 })));
+    drop(file);
+    assert_eq!(fs::read(&path).unwrap(), source_content);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn apply_in_place_rolls_back_on_wrong_target(){
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let mut patch = UpsPatch::load(&load_file_content(patch_path)).unwrap();
+        let target_crc32 = patch.target_crc32;
+        patch.target_crc32 ^= 1;
+        let expected_error = UpsError::Apply(TargetMismatch { expected: target_crc32 ^ 1, actual: target_crc32 });
+        let path = temp_path("in_place_wrong_target.txt");
+
+        fs::write(&path, &source_content).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        assert_eq!(patch.apply_in_place(&mut file, true), Err(expected_error));
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), source_content);
+
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        assert!(patch.apply_in_place(&mut file, false).is_err());
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), load_file_content(target_path));
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn compose_throws_incompatible_patches_error(){
+    let patch_1 = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let patch_2 = UpsPatch::load(&load_file_content(PATCH_PATH_2)).unwrap();
+    assert_eq!(UpsPatch::compose(&patch_1, &patch_2), Err(UpsError::Create(IncompatiblePatches {
+        expected_crc32: 0x29e0b36e,
+        actual_crc32: 0x23a777e3,
+        expected_size: 28,
+        actual_size: 27,
+    })));
+}
+
+#[test]
+fn ips_throws_not_ips_file_error(){
+    let patch_content = load_file_content(PATCH_PATH_1);
+    assert_eq!(IpsPatch::load(&patch_content), Err(UpsError::Load(IsNotIpsFile)));
+}
+
+#[test]
+fn ips_throws_corrupt_file_error(){
+    let truncated_record = b"PATCH\x00\x00\x11\x00\x05ab".to_vec();
+    assert_eq!(IpsPatch::load(&truncated_record), Err(UpsError::Load(Truncated { offset: 5 })));
+    let missing_footer = b"PATCH\x00\x00\x11\x00\x02ab".to_vec();
+    assert_eq!(IpsPatch::load(&missing_footer), Err(UpsError::Load(Truncated { offset: 12 })));
+    let bad_truncation = b"PATCHEOF\x00\x01".to_vec();
+    assert_eq!(IpsPatch::load(&bad_truncation), Err(UpsError::Load(UnexpectedData { offset: 8 })));
+}
+
+#[test]
+fn ips_throws_file_too_big_error(){
+    let source_content = vec![0u8; 4];
+    let target_content = vec![1u8; 0x1000001];
+    assert_eq!(IpsPatch::create(&source_content, &target_content), Err(UpsError::Create(FileTooBig { size: 0x1000001, max_size: 0x1000000 })));
+}
+
+#[test]
+fn bps_throws_not_bps_file_error(){
+    let patch_content = load_file_content(PATCH_PATH_1);
+    assert_eq!(BpsPatch::load(&patch_content), Err(UpsError::Load(IsNotBpsFile)));
+}
+
+#[test]
+fn bps_throws_corrupt_file_error(){
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let mut patch_content = BpsPatch::create(&source_content, &target_content, &[]).get_patch_file_contents();
+    patch_content[6] ^= 0x01;
+    assert!(matches!(BpsPatch::load(&patch_content), Err(UpsError::Load(IsCorrupted { .. }))));
+    assert_eq!(BpsPatch::load(b"BPS1\x80\x80"), Err(UpsError::Load(Truncated { offset: 0 })));
+}
+
+#[test]
+fn bps_throws_wrong_source_error(){
+    let mut source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let patch = BpsPatch::create(&source_content, &target_content, &[]);
+    source_content[7] += 1;
+    assert_eq!(patch.apply(&source_content), Err(UpsError::Apply(SourceMismatch { expected: 0x29e0b36e, actual: 0xb63a30f0 })));
+}
+
+#[test]
+fn bps_throws_out_of_bounds_error(){
+    let mut patch = BpsPatch::create(&[], b"abcdabcd", &[]);
+    patch.actions = vec![BpsAction::TargetCopy { length: 4, offset: 0 }];
+    assert_eq!(patch.apply_no_check(&[]), Err(UpsError::Apply(OutOfBounds { offset: 0 })));
+    patch.actions = vec![BpsAction::SourceCopy { length: 4, offset: -1 }];
+    assert_eq!(patch.apply_no_check(b"abcd"), Err(UpsError::Apply(OutOfBounds { offset: 0 })));
+    patch.actions = vec![BpsAction::SourceRead { length: 9 }];
+    assert_eq!(patch.apply_no_check(b"abcdabcdabcd"), Err(UpsError::Apply(OutOfBounds { offset: 0 })));
+    patch.actions = vec![
+        BpsAction::TargetRead { data: b"abcd".to_vec() },
+        BpsAction::TargetCopy { length: 4, offset: 4 },
+    ];
+    assert_eq!(patch.apply_no_check(&[]), Err(UpsError::Apply(OutOfBounds { offset: 4 })));
+}
+
+#[test]
+fn bps_throws_target_size_mismatch_error(){
+    let mut patch = BpsPatch::create(&[], b"abcdabcd", &[]);
+    patch.actions = vec![BpsAction::SourceRead { length: 4 }];
+    assert_eq!(patch.apply_no_check(b"abcd"), Err(UpsError::Apply(TargetSizeMismatch { expected: 8, actual: 4 })));
+}
+
+#[test]
+fn bps_survives_huge_declared_sizes(){
+    let mut patch = BpsPatch::create(&[], b"abcdabcd", &[]);
+    patch.target_file_size = i64::MAX as u64;
+    assert_eq!(patch.apply(&[]), Err(UpsError::Apply(TargetSizeMismatch { expected: i64::MAX as u64, actual: 8 })));
+    patch.actions.push(BpsAction::TargetCopy { length: 1 << 62, offset: -8 });
+    assert_eq!(patch.apply(&[]), Err(UpsError::Apply(TooBig { size: i64::MAX as u64 })));
+}
+
+#[test]
+fn load_any_throws_unknown_format_errors(){
+    let source_content = load_file_content(SOURCE_PATH);
+    assert_eq!(load_any(&source_content).err(), Some(UpsError::Load(IsNotPatchFile)));
+    assert_eq!(load_any(&[0xD6, 0xC3, 0xC4, 0x00]).err(), Some(UpsError::Load(UnsupportedFormat)));
+    assert_eq!(load_any(b"PPF30\x02").err(), Some(UpsError::Load(UnsupportedFormat)));
+}
+#[test]
+fn errors_display_their_details(){
+    let error = UpsError::Apply(SourceMismatch { expected: 0x29e0b36e, actual: 0xb63a30f0 });
+    assert_eq!(error.to_string(), "Ups error: Source file doesn't match crc32 for source file (expected 29e0b36e, found b63a30f0)");
+    let error = UpsError::Load(UnterminatedHunk { offset: 6 });
+    assert_eq!(error.to_string(), "Ups error: File provided contains a hunk that doesn't end at byte 6");
+    assert_eq!(error.message(), "File provided contains a hunk that doesn't end");
+}
+
+#[test]
+fn io_errors_are_the_source_of_ups_errors(){
+    let error = UpsError::from(io::Error::new(io::ErrorKind::NotFound, "missing file"));
+    assert_eq!(error.source().unwrap().to_string(), "missing file");
+    assert!(UpsError::Load(IsNotUpsFile).source().is_none());
+}
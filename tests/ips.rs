@@ -0,0 +1,92 @@
+use ups::{IpsPatch, IpsRecord};
+mod common;
+use common::*;
+
+fn hand_made_patch() -> Vec<u8> {
+    let mut content = b"PATCH".to_vec();
+    content.extend([0x00, 0x00, 0x11, 0x00, 0x02, b'a', b'b']);
+    content.extend([0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x04, 0x2A]);
+    content.extend(b"EOF");
+    content.extend([0x00, 0x00, 0x22]);
+    content
+}
+
+#[test]
+fn can_load_ips_patch() {
+    let patch = IpsPatch::load(&hand_made_patch()).unwrap();
+    assert_eq!(
+        patch.records,
+        vec![
+            IpsRecord::Data { offset: 0x11, data: vec![b'a', b'b'] },
+            IpsRecord::Rle { offset: 0x20, length: 4, value: 0x2A },
+        ]
+    );
+    assert_eq!(patch.truncate_size, Some(0x22));
+    assert_eq!(patch.get_patch_file_contents(), hand_made_patch());
+}
+
+#[test]
+fn can_apply_ips_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let patch = IpsPatch::load(&hand_made_patch()).unwrap();
+
+    let mut expected = source_content.clone();
+    expected[0x11] = b'a';
+    expected[0x12] = b'b';
+    expected.extend([0x00, 0x00, 0x00, 0x00, 0x2A, 0x2A]);
+    assert_eq!(patch.apply(&source_content), expected);
+}
+
+#[test]
+fn can_create_ips_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for target_path in &[TARGET_PATH_1, TARGET_PATH_2] {
+        let target_content = load_file_content(target_path);
+        let patch = IpsPatch::create(&source_content, &target_content).unwrap();
+        assert_eq!(patch.apply(&source_content), target_content);
+        assert_eq!(IpsPatch::load(&patch.get_patch_file_contents()).unwrap(), patch);
+    }
+    let patch_1 = IpsPatch::create(&source_content, &load_file_content(TARGET_PATH_1)).unwrap();
+    assert_eq!(patch_1.truncate_size, Some(27));
+    let patch_2 = IpsPatch::create(&source_content, &load_file_content(TARGET_PATH_2)).unwrap();
+    assert_eq!(patch_2.truncate_size, None);
+}
+
+#[test]
+fn can_create_rle_records() {
+    let source_content = vec![0u8; 100];
+    let mut target_content = source_content.clone();
+    target_content[10] = 0x01;
+    for byte in &mut target_content[11..41] {
+        *byte = 0xFF;
+    }
+    target_content[41] = 0x02;
+    let patch = IpsPatch::create(&source_content, &target_content).unwrap();
+    assert_eq!(
+        patch.records,
+        vec![
+            IpsRecord::Data { offset: 10, data: vec![0x01] },
+            IpsRecord::Rle { offset: 11, length: 30, value: 0xFF },
+            IpsRecord::Data { offset: 41, data: vec![0x02] },
+        ]
+    );
+    assert_eq!(patch.apply(&source_content), target_content);
+}
+
+#[test]
+fn can_create_records_around_eof_offset() {
+    let source_content = vec![0u8; 0x454F50];
+    let mut target_content = source_content.clone();
+    for byte in &mut target_content[0x454F30..] {
+        *byte = 0x02;
+    }
+    for byte in &mut target_content[0x454F46..0x454F48] {
+        *byte = 0x01;
+    }
+    target_content.push(0x03);
+    let patch = IpsPatch::create(&source_content, &target_content).unwrap();
+    assert_eq!(patch.records[1], IpsRecord::Data { offset: 0x454F45, data: [vec![0x02, 0x01, 0x01], vec![0x02; 8], vec![0x03]].concat() });
+    let loaded_patch = IpsPatch::load(&patch.get_patch_file_contents()).unwrap();
+    assert_eq!(loaded_patch, patch);
+    assert_eq!(loaded_patch.apply(&source_content), target_content);
+}
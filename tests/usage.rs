@@ -1,4 +1,10 @@
-use ups::UpsPatch;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Write};
+use ups::{
+    detect, load_any, ApplyOptions, BpsPatch, Crc32, CreateOptions, Direction, FileVerdict, HeaderFixup, IpsPatch, PatchFormat, RomHeader,
+    SourceDiagnosis, SourceTransform, UpsError, UpsPatch, UpsReader, UpsWriter,
+};
+use ups::ApplyError::{self, SourceSizeMismatch};
 mod common;
 use common::*;
 #[test]
@@ -48,6 +54,49 @@ fn can_verify_source() {
     assert!(patch_2.file_is_source(&source_content));
 }
 #[test]
+fn can_hash_files_incrementally() {
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let mut crc32 = Crc32::new();
+    io::copy(&mut File::open(SOURCE_PATH).unwrap(), &mut crc32).unwrap();
+    assert_eq!(crc32.finish(), patch.source_crc32);
+
+    let target_content = load_file_content(TARGET_PATH_1);
+    let mut crc32 = Crc32::default();
+    for piece in target_content.chunks(7) {
+        crc32.update(piece);
+    }
+    assert_eq!(crc32.finish(), patch.target_crc32);
+    crc32.write_all(b"more data").unwrap();
+    assert_ne!(crc32.finish(), patch.target_crc32);
+
+    assert_eq!(Crc32::new().finish(), 0);
+}
+#[test]
+fn can_check_files_without_loading_them() {
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    let target_2_content = load_file_content(TARGET_PATH_2);
+    assert_eq!(patch.check_source_file(SOURCE_PATH).unwrap(), FileVerdict::Source);
+    assert_eq!(patch.check_target_file(TARGET_PATH_1).unwrap(), FileVerdict::Target);
+    assert_eq!(patch.check_source_file(TARGET_PATH_1).unwrap(), FileVerdict::Target);
+    assert_eq!(patch.check_target_reader(File::open(SOURCE_PATH).unwrap()).unwrap(), FileVerdict::Source);
+
+    let mut wrong_content = load_file_content(SOURCE_PATH);
+    wrong_content[3] ^= 0x01;
+    assert_eq!(
+        patch.check_source_reader(Cursor::new(&wrong_content)).unwrap(),
+        FileVerdict::Crc32Mismatch { expected: patch.source_crc32, actual: 0x8c6b2360 }
+    );
+    wrong_content.push(0);
+    assert_eq!(
+        patch.check_source_reader(&wrong_content[..]).unwrap(),
+        FileVerdict::SizeMismatch { expected: patch.source_file_size, actual: patch.source_file_size + 1 }
+    );
+    assert_eq!(
+        patch.check_target_reader(&target_2_content[..]).unwrap(),
+        FileVerdict::SizeMismatch { expected: patch.target_file_size, actual: target_2_content.len() as u64 }
+    );
+}
+#[test]
 fn can_verify_target() {
     let content_1 = load_file_content(PATCH_PATH_1);
     let patch_1 = UpsPatch::load(&content_1).unwrap();
@@ -85,8 +134,75 @@ fn can_create_patch() {
     );
 }
 
-/*#[test]
-fn can_create_patch(){
+#[test]
+fn can_create_patch_with_options() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let patch = UpsPatch::create(&source_content, &target_content);
+    for options in [CreateOptions::default(), CreateOptions::fastest()] {
+        assert_eq!(UpsPatch::create_with_options(&source_content, &target_content, &options), patch);
+    }
+    assert_eq!(CreateOptions::smallest(), CreateOptions { drop_source_tail: true, ..CreateOptions::default() });
+
+    // An unchanged byte between two hunks only costs the offset of the second one, as much as
+    // a changed byte would cost inside a single hunk
+    let source_content = vec![0x11; 64];
+    let mut target_content = source_content.clone();
+    target_content[10..20].copy_from_slice(&[0x22; 10]);
+    target_content[21..30].copy_from_slice(&[0x22; 9]);
+    let split_patch = UpsPatch::create_with_options(&source_content, &target_content, &CreateOptions::smallest());
+    assert_eq!(split_patch.changes.len(), 2);
+    target_content[20] = 0x22;
+    let merged_patch = UpsPatch::create_with_options(&source_content, &target_content, &CreateOptions::smallest());
+    assert_eq!(merged_patch.changes.len(), 1);
+    assert_eq!(split_patch.get_patch_file_contents().len(), merged_patch.get_patch_file_contents().len() + 1);
+}
+
+#[test]
+fn can_drop_source_tail() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let drop_source_tail = CreateOptions { drop_source_tail: true, ..CreateOptions::default() };
+
+    // Shrinking, the end of the source only matters to revert the patch
+    let target_content = source_content[..10].to_vec();
+    let patch = UpsPatch::create(&source_content, &target_content);
+    let smaller_patch = UpsPatch::create_with_options(&source_content, &target_content, &drop_source_tail);
+    assert_eq!(patch.get_patch_file_contents().len(), 38);
+    assert_eq!(smaller_patch.get_patch_file_contents().len(), 18);
+    assert!(smaller_patch.changes.is_empty());
+    assert_eq!(smaller_patch, UpsPatch::create_with_options(&source_content, &target_content, &CreateOptions::smallest()));
+    assert_eq!(smaller_patch.apply(&source_content).unwrap(), target_content);
+    assert_eq!(UpsPatch::load(&smaller_patch.get_patch_file_contents()).unwrap(), smaller_patch);
+    assert_eq!(patch.revert(&target_content).unwrap(), source_content);
+    assert!(matches!(smaller_patch.revert(&target_content), Err(UpsError::Apply(ApplyError::TargetMismatch { .. }))));
+
+    // Neither can the patches that get the source back from the target
+    let target_to_source = UpsPatch::create(&target_content, &source_content);
+    assert_eq!(patch.invert().apply(&target_content).unwrap(), source_content);
+    assert!(matches!(smaller_patch.invert().apply(&target_content), Err(UpsError::Apply(ApplyError::TargetMismatch { .. }))));
+    assert_eq!(UpsPatch::compose(&patch, &target_to_source).unwrap().apply(&source_content).unwrap(), source_content);
+    let composed_patch = UpsPatch::compose(&smaller_patch, &target_to_source).unwrap();
+    assert!(matches!(composed_patch.apply(&source_content), Err(UpsError::Apply(ApplyError::TargetMismatch { .. }))));
+
+    // A hunk that crosses the end of the target keeps the part before it
+    let mut target_content = source_content[..20].to_vec();
+    target_content[15..].copy_from_slice(b"=====");
+    let patch = UpsPatch::create(&source_content, &target_content);
+    let smaller_patch = UpsPatch::create_with_options(&source_content, &target_content, &drop_source_tail);
+    assert!(smaller_patch.get_patch_file_contents().len() < patch.get_patch_file_contents().len());
+    assert_eq!(smaller_patch.changes.last().map(|(offset, xor_bytes)| offset + xor_bytes.len() as u64), Some(20));
+    assert_eq!(smaller_patch.apply(&source_content).unwrap(), target_content);
+
+    // Growing, there is no source tail to drop
+    let mut target_content = source_content.clone();
+    target_content.extend(b" and some more");
+    let patch = UpsPatch::create(&source_content, &target_content);
+    assert_eq!(UpsPatch::create_with_options(&source_content, &target_content, &drop_source_tail), patch);
+    assert_eq!(patch.get_patch_file_contents().len(), 34);
+}
+
+#[test]
+fn can_serialize_patch() {
     let source_content = load_file_content(SOURCE_PATH);
 
     let target_content_1 = load_file_content(TARGET_PATH_1);
@@ -98,7 +214,67 @@ fn can_create_patch(){
     let patch_file_content_2 = load_file_content(PATCH_PATH_2);
     let created_patch_2 = UpsPatch::create(&source_content, &target_content_2);
     assert_eq!(created_patch_2.get_patch_file_contents(), patch_file_content_2);
-}*/
+
+    let mut written: Vec<u8> = vec![];
+    created_patch_2.to_writer(&mut written).unwrap();
+    assert_eq!(written, patch_file_content_2);
+}
+
+#[test]
+fn can_round_trip_generated_patches() {
+    let mut state: u64 = 0xD1B54A32D192ED03;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..500 {
+        let source_content: Vec<u8> = (0..next() % 300).map(|_| next() as u8).collect();
+        let mut target_content = source_content.clone();
+        target_content.resize((next() % 300) as usize, 0);
+        for _ in 0..next() % 20 {
+            if !target_content.is_empty() {
+                let i = next() as usize % target_content.len();
+                target_content[i] = next() as u8;
+            }
+        }
+
+        let patch = UpsPatch::create(&source_content, &target_content);
+        let patch_content = patch.get_patch_file_contents();
+        assert_eq!(UpsPatch::load(&patch_content).unwrap(), patch);
+        assert_eq!(&patch_content[patch_content.len() - 4..], patch.patch_crc32.to_le_bytes());
+        assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+    }
+}
+
+#[test]
+fn can_serialize_hand_made_changes() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let created_patch = UpsPatch::create(&source_content, &target_content);
+    let (offset, xor_bytes) = created_patch.changes[0].clone();
+
+    // The same hunk, out of order, split in touching parts and with two overlapping bytes
+    // cancelling each other
+    let mut hand_made_patch = UpsPatch::create(&source_content, &target_content);
+    hand_made_patch.changes = vec![
+        (offset + 5, xor_bytes[5..].to_vec()),
+        (offset, xor_bytes[..5].to_vec()),
+        (offset + 3, vec![0x33]),
+        (offset + 3, vec![0x33]),
+    ];
+    hand_made_patch.patch_crc32 = 0;
+    assert_eq!(hand_made_patch.apply_no_check(&source_content).unwrap(), target_content);
+    assert_eq!(UpsPatch::load(&hand_made_patch.get_patch_file_contents()).unwrap(), created_patch);
+
+    // XOR bytes that cancel out leave a byte unchanged, so the hunk gets split around it
+    hand_made_patch.changes.push((offset + 3, vec![xor_bytes[3]]));
+    assert_eq!(UpsPatch::load(&hand_made_patch.get_patch_file_contents()).unwrap().changes, vec![
+        (offset, xor_bytes[..3].to_vec()),
+        (offset + 4, xor_bytes[4..].to_vec()),
+    ]);
+}
 
 #[test]
 fn can_apply_no_test() {
@@ -107,13 +283,13 @@ fn can_apply_no_test() {
     let target_content_1 = load_file_content(TARGET_PATH_1);
     let patch_file_content_1 = load_file_content(PATCH_PATH_1);
     let patch_1 = UpsPatch::load(&patch_file_content_1).unwrap();
-    let final_file_content_1 = patch_1.apply_no_check(&source_content);
+    let final_file_content_1 = patch_1.apply_no_check(&source_content).unwrap();
     assert_eq!(final_file_content_1, target_content_1);
 
     let target_content_2 = load_file_content(TARGET_PATH_2);
     let patch_file_content_2 = load_file_content(PATCH_PATH_2);
     let patch_2 = UpsPatch::load(&patch_file_content_2).unwrap();
-    let final_file_content_2 = patch_2.apply_no_check(&source_content);
+    let final_file_content_2 = patch_2.apply_no_check(&source_content).unwrap();
 
     assert_eq!(final_file_content_2, target_content_2);
 }
@@ -134,3 +310,429 @@ fn can_apply() {
     let final_file_content_2 = patch_2.apply(&source_content).unwrap();
     assert_eq!(final_file_content_2, target_content_2);
 }
+
+#[test]
+fn can_fix_rom_headers() {
+    let fix_headers = ApplyOptions { fix_headers: true, ..ApplyOptions::default() };
+    let rom: Vec<u8> = (0..16384u32).map(|i| (i % 253) as u8).collect();
+    let mut patched_rom = rom.clone();
+    patched_rom[100..200].copy_from_slice(&[0x42; 100]);
+    patched_rom.extend(vec![0x24; 1024]);
+    let patch = UpsPatch::create(&rom, &patched_rom);
+
+    // Headers get stripped, and put back in the result
+    let snes_header = vec![0x55; 512];
+    let ines_header = b"NES\x1a\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    for (header, kind) in [(&snes_header, RomHeader::SnesCopier), (&ines_header, RomHeader::Ines)] {
+        let headered_rom = [&header[..], &rom[..]].concat();
+        assert_eq!(
+            patch.apply(&headered_rom).unwrap_err(),
+            UpsError::Apply(SourceSizeMismatch { expected: 16384, actual: 16384 + header.len() as u64 })
+        );
+        let (output, fixup) = patch.apply_with_fixup(&headered_rom, &fix_headers).unwrap();
+        assert_eq!(output, [&header[..], &patched_rom[..]].concat());
+        assert_eq!(fixup, Some(HeaderFixup::Stripped(kind)));
+        assert_eq!(patch.apply_with_options(&headered_rom, &fix_headers).unwrap(), output);
+    }
+
+    // Patches made for headered files get a header added, which isn't in the result, this one
+    // with the size of the ROM in 8 KiB blocks
+    let smc_header = [&[0x02, 0x00][..], &[0x00; 510][..]].concat();
+    let headered_patch = UpsPatch::create(&[&smc_header[..], &rom[..]].concat(), &[&smc_header[..], &patched_rom[..]].concat());
+    let (output, fixup) = headered_patch.apply_with_fixup(&rom, &fix_headers).unwrap();
+    assert_eq!(output, patched_rom);
+    assert_eq!(fixup, Some(HeaderFixup::Added(RomHeader::SnesCopier)));
+
+    let disk: Vec<u8> = (0..65500u32).map(|i| (i % 241) as u8).collect();
+    let mut patched_disk = disk.clone();
+    patched_disk[0x1000] = 0xff;
+    let fds_header = b"FDS\x1a\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    let fds_patch = UpsPatch::create(&[&fds_header[..], &disk[..]].concat(), &[&fds_header[..], &patched_disk[..]].concat());
+    let (output, fixup) = fds_patch.apply_with_fixup(&disk, &fix_headers).unwrap();
+    assert_eq!(output, patched_disk);
+    assert_eq!(fixup, Some(HeaderFixup::Added(RomHeader::Fds)));
+    let (output, fixup) = patch.apply_with_fixup(&rom, &fix_headers).unwrap();
+    assert_eq!((output, fixup), (patched_rom, None));
+
+    // Files that no header explains keep failing as usual
+    let wrong_rom = [&[0x00; 512][..], &rom[1..]].concat();
+    assert_eq!(
+        patch.apply_with_fixup(&wrong_rom, &fix_headers).unwrap_err(),
+        UpsError::Apply(SourceSizeMismatch { expected: 16384, actual: 16895 })
+    );
+}
+
+#[test]
+fn can_diagnose_sources() {
+    let rom: Vec<u8> = (0..32768u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut patched_rom = rom.clone();
+    patched_rom[0x2000..0x2100].copy_from_slice(&[0x42; 0x100]);
+    let patch = UpsPatch::create(&rom, &patched_rom);
+    assert_eq!(patch.diagnose_source(&rom), SourceDiagnosis::AsIs(FileVerdict::Source));
+    assert_eq!(patch.diagnose_source(&patched_rom), SourceDiagnosis::AsIs(FileVerdict::Target));
+
+    let v64: Vec<u8> = rom.chunks(2).flat_map(|pair| vec![pair[1], pair[0]]).collect();
+    let n64: Vec<u8> = rom.chunks(4).flat_map(|word| word.iter().rev().copied().collect::<Vec<u8>>()).collect();
+    let mut smd = vec![0u8; 512];
+    for block in rom.chunks(16384) {
+        smd.extend(block.iter().skip(1).step_by(2));
+        smd.extend(block.iter().step_by(2));
+    }
+    let mut overdump = rom.clone();
+    overdump.extend(vec![0xff; 32768]);
+    let headered = [&[0x00; 512][..], &rom[..]].concat();
+    for (content, transform) in [
+        (&v64, SourceTransform::ByteSwap),
+        (&n64, SourceTransform::WordSwap),
+        (&smd, SourceTransform::Deinterleave),
+        (&overdump, SourceTransform::TrimOverdump),
+        (&headered, SourceTransform::Header(HeaderFixup::Stripped(RomHeader::SnesCopier))),
+    ] {
+        let diagnosis = patch.diagnose_source(content);
+        assert_eq!(diagnosis, SourceDiagnosis::Repairable { transform, content: rom.clone() });
+    }
+
+    // Trimmed ROMs get their 0xFF bytes back
+    let mut full_rom = rom.clone();
+    full_rom.extend(vec![0xff; 1000]);
+    let patch = UpsPatch::create(&full_rom, &patched_rom);
+    match patch.diagnose_source(&rom) {
+        SourceDiagnosis::Repairable { transform, content } => {
+            assert_eq!(transform, SourceTransform::PadWithFf);
+            assert_eq!(patch.apply(&content).unwrap(), patched_rom);
+        }
+        diagnosis => panic!("{:?}", diagnosis),
+    }
+
+    let mut wrong_rom = rom.clone();
+    wrong_rom[0] ^= 0x01;
+    assert_eq!(
+        patch.diagnose_source(&wrong_rom),
+        SourceDiagnosis::AsIs(FileVerdict::SizeMismatch { expected: 33768, actual: 32768 })
+    );
+}
+
+#[test]
+fn can_apply_stream() {
+    let source_content = load_file_content(SOURCE_PATH);
+
+    let target_content_1 = load_file_content(TARGET_PATH_1);
+    let patch_file_content_1 = load_file_content(PATCH_PATH_1);
+    let patch_1 = UpsPatch::load(&patch_file_content_1).unwrap();
+    let mut final_file_content_1: Vec<u8> = vec![];
+    patch_1.apply_stream(&mut Cursor::new(&source_content), &mut final_file_content_1).unwrap();
+    assert_eq!(final_file_content_1, target_content_1);
+
+    let target_content_2 = load_file_content(TARGET_PATH_2);
+    let patch_file_content_2 = load_file_content(PATCH_PATH_2);
+    let patch_2 = UpsPatch::load(&patch_file_content_2).unwrap();
+    let mut final_file_content_2: Vec<u8> = vec![];
+    patch_2.apply_stream(&mut Cursor::new(&source_content), &mut final_file_content_2).unwrap();
+    assert_eq!(final_file_content_2, target_content_2);
+}
+
+#[test]
+fn can_apply_stream_across_buffer_boundaries() {
+    let source_content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut target_content = source_content.clone();
+    for byte in &mut target_content[65_530..65_545] {
+        *byte = !*byte;
+    }
+    target_content.truncate(150_000);
+    target_content.extend(vec![0x42; 100]);
+    let patch = UpsPatch::create(&source_content, &target_content);
+
+    let mut output: Vec<u8> = vec![];
+    patch.apply_stream(&mut Cursor::new(&source_content), &mut output).unwrap();
+    assert_eq!(output, target_content);
+
+    let bigger_target = [source_content.as_slice(), &[0x13; 70_000]].concat();
+    let patch = UpsPatch::create(&source_content, &bigger_target);
+    let mut output: Vec<u8> = vec![];
+    patch.apply_stream(&mut Cursor::new(&source_content), &mut output).unwrap();
+    assert_eq!(output, bigger_target);
+}
+
+#[test]
+fn can_read_patch_stream() {
+    for patch_path in &[PATCH_PATH_1, PATCH_PATH_2] {
+        let patch_content = load_file_content(patch_path);
+        let patch = UpsPatch::load(&patch_content).unwrap();
+        let mut reader = UpsReader::new(Cursor::new(&patch_content)).unwrap();
+        assert_eq!(reader.source_file_size(), patch.source_file_size);
+        assert_eq!(reader.target_file_size(), patch.target_file_size);
+        assert_eq!(reader.source_crc32(), None);
+
+        let changes: Vec<(u64, Vec<u8>)> = reader.by_ref().map(|hunk| hunk.unwrap()).collect();
+        assert_eq!(changes, patch.changes);
+        assert_eq!(reader.source_crc32(), Some(patch.source_crc32));
+        assert_eq!(reader.target_crc32(), Some(patch.target_crc32));
+        assert_eq!(reader.patch_crc32(), Some(patch.patch_crc32));
+    }
+}
+
+#[test]
+fn can_apply_patch_stream() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let patch_content = load_file_content(patch_path);
+        let reader = UpsReader::new(Cursor::new(&patch_content)).unwrap();
+        let mut target_content: Vec<u8> = vec![];
+        reader.apply_stream(&mut Cursor::new(&source_content), &mut target_content).unwrap();
+        assert_eq!(target_content, load_file_content(target_path));
+    }
+}
+
+#[test]
+fn can_create_patch_stream() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let target_content = load_file_content(target_path);
+        let mut patch_content: Vec<u8> = vec![];
+        UpsPatch::create_stream(
+            &mut Cursor::new(&source_content),
+            &mut Cursor::new(&target_content),
+            &mut patch_content,
+        ).unwrap();
+        assert_eq!(patch_content, load_file_content(patch_path));
+    }
+}
+
+#[test]
+fn can_create_patch_stream_across_buffer_boundaries() {
+    let source_content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut target_content = source_content.clone();
+    for byte in &mut target_content[65_530..65_545] {
+        *byte = !*byte;
+    }
+    for byte in &mut target_content[131_000..140_000] {
+        *byte = !*byte;
+    }
+    target_content.extend(vec![0x42; 70_000]);
+
+    let mut patch_content: Vec<u8> = vec![];
+    UpsPatch::create_stream(
+        &mut Cursor::new(&source_content),
+        &mut Cursor::new(&target_content),
+        &mut patch_content,
+    ).unwrap();
+    let patch = UpsPatch::load(&patch_content).unwrap();
+    assert_eq!(patch, UpsPatch::create(&source_content, &target_content));
+    assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn can_create_patch_in_parallel() {
+    let patch = UpsPatch::create_parallel(&load_file_content(SOURCE_PATH), &load_file_content(TARGET_PATH_1));
+    assert_eq!(patch, UpsPatch::create(&load_file_content(SOURCE_PATH), &load_file_content(TARGET_PATH_1)));
+
+    // Changes that cross the 1 MiB chunks the files are split into, and a longer target
+    let source_content: Vec<u8> = (0..3_500_000u32).map(|i| (i % 251) as u8).collect();
+    let mut target_content = source_content.clone();
+    for range in [(1 << 20) - 7..(1 << 20) + 9, (2 << 20) - 1..(2 << 20), (2 << 20) + 3..(3 << 20) + 5] {
+        for byte in &mut target_content[range] {
+            *byte = !*byte;
+        }
+    }
+    target_content.extend(vec![0x42; 1_000_000]);
+    for (source, target) in [(&source_content, &target_content), (&target_content, &source_content)] {
+        let patch = UpsPatch::create_parallel(source, target);
+        assert_eq!(patch, UpsPatch::create(source, target));
+        assert_eq!(patch.get_patch_file_contents(), UpsPatch::create(source, target).get_patch_file_contents());
+        assert_eq!(&patch.apply(source).unwrap(), target);
+    }
+    assert_eq!(UpsPatch::create_parallel(&[], &[]), UpsPatch::create(&[], &[]));
+}
+
+#[test]
+fn can_write_patch_by_hunks() {
+    let loaded_patch = UpsPatch::load(&load_file_content(PATCH_PATH_2)).unwrap();
+    let mut writer = UpsWriter::new(vec![], loaded_patch.source_file_size, loaded_patch.target_file_size).unwrap();
+    for (offset, xor_bytes) in &loaded_patch.changes {
+        writer.write_hunk(*offset, xor_bytes).unwrap();
+    }
+    let patch_content = writer.finish(loaded_patch.source_crc32, loaded_patch.target_crc32).unwrap();
+    assert_eq!(patch_content, load_file_content(PATCH_PATH_2));
+}
+
+#[test]
+fn can_load_patch_for_files_beyond_4_gib() {
+    let source_file_size: u64 = 4_700_000_000;
+    let target_file_size: u64 = 25_000_000_000;
+    let hunks: Vec<(u64, Vec<u8>)> = vec![(0x1_0000_0000, vec![0x01, 0x02]), (20_000_000_000, vec![0xff])];
+    let mut writer = UpsWriter::new(vec![], source_file_size, target_file_size).unwrap();
+    for (offset, xor_bytes) in &hunks {
+        writer.write_hunk(*offset, xor_bytes).unwrap();
+    }
+    let patch_content = writer.finish(0x12345678, 0x9abcdef0).unwrap();
+
+    let patch = UpsPatch::load(&patch_content).unwrap();
+    assert_eq!(patch.source_file_size, source_file_size);
+    assert_eq!(patch.target_file_size, target_file_size);
+    assert_eq!(patch.changes, hunks);
+
+    let mut reader = UpsReader::new(Cursor::new(&patch_content)).unwrap();
+    assert_eq!(reader.target_file_size(), target_file_size);
+    assert_eq!((&mut reader).collect::<Result<Vec<_>, _>>().unwrap(), hunks);
+}
+
+#[test]
+fn can_apply_in_place() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let patch = UpsPatch::load(&load_file_content(patch_path)).unwrap();
+        let path = temp_path("in_place.txt");
+        fs::write(&path, &source_content).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        patch.apply_in_place(&mut file, true).unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), load_file_content(target_path));
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn can_summarize_patch() {
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_2)).unwrap();
+    let summary = patch.summary();
+    assert_eq!(summary.hunk_count, 3);
+    assert_eq!(summary.changed_bytes, 13);
+    assert_eq!(summary.largest_hunk, Some(26..34));
+    assert_eq!(summary.ranges, vec![17..18, 19..23, 26..34]);
+    assert_eq!(summary.size_delta, 6);
+    assert_eq!(summary.target_crc32, 0xE1CFB1EB);
+
+    let summary = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap().summary();
+    assert_eq!(summary.size_delta, -1);
+    assert_eq!(summary.to_string(), "Source size:   28 bytes
+Target size:   27 bytes (-1)
+Source crc32:  29e0b36e
+Target crc32:  23a777e3
+Patch crc32:   ffa6802b
+Hunks:         1
+Changed bytes: 11
+Largest hunk:  11 bytes at 0x11");
+}
+
+#[test]
+fn can_dump_patch() {
+    let patch = UpsPatch::load(&load_file_content(PATCH_PATH_1)).unwrap();
+    assert_eq!(patch.dump(None), "Hunk 0 at 0x00000011, 11 bytes
+  xor    15 06 1b 13 0f 45 46 0f 05 09 65
+");
+    assert_eq!(patch.dump(Some(&load_file_content(SOURCE_PATH))), "Hunk 0 at 0x00000011, 11 bytes
+  xor    15 06 1b 13 0f 45 46 0f 05 09 65
+  source 73 6f 75 72 63 65 20 66 69 6c 65
+  target 66 69 6e 61 6c 20 66 69 6c 65 --
+");
+}
+
+#[test]
+fn can_compose_patches() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content_1 = load_file_content(TARGET_PATH_1);
+    let target_content_2 = load_file_content(TARGET_PATH_2);
+    let final1_to_source = UpsPatch::create(&target_content_1, &source_content);
+    let source_to_final2 = UpsPatch::load(&load_file_content(PATCH_PATH_2)).unwrap();
+    let composed_patch = UpsPatch::compose(&final1_to_source, &source_to_final2).unwrap();
+    assert_eq!(composed_patch, UpsPatch::create(&target_content_1, &target_content_2));
+    assert_eq!(composed_patch.apply(&target_content_1).unwrap(), target_content_2);
+
+    let mut state: u64 = 0x8CB92BA72F3D8DD7;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..300 {
+        // Each version is the previous one with some bytes changed and a different size
+        let mut versions: Vec<Vec<u8>> = vec![(0..next() % 200).map(|_| next() as u8).collect()];
+        for _ in 0..2 {
+            let mut version = versions.last().unwrap().clone();
+            version.resize((next() % 200) as usize, next() as u8);
+            for _ in 0..next() % 10 {
+                if !version.is_empty() {
+                    let i = next() as usize % version.len();
+                    version[i] = next() as u8;
+                }
+            }
+            versions.push(version);
+        }
+        let first = UpsPatch::create(&versions[0], &versions[1]);
+        let second = UpsPatch::create(&versions[1], &versions[2]);
+        assert_eq!(UpsPatch::compose(&first, &second).unwrap(), UpsPatch::create(&versions[0], &versions[2]));
+    }
+}
+
+#[test]
+fn can_invert_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let patch = UpsPatch::load(&load_file_content(patch_path)).unwrap();
+        let target_content = load_file_content(target_path);
+        let inverted_patch = patch.invert();
+        assert_eq!(inverted_patch.apply(&target_content).unwrap(), source_content);
+        assert_eq!(inverted_patch, UpsPatch::create(&target_content, &source_content));
+        assert_eq!(UpsPatch::load(&inverted_patch.get_patch_file_contents()).unwrap(), inverted_patch);
+        assert_eq!(inverted_patch.invert(), patch);
+    }
+}
+
+#[test]
+fn can_revert() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let patch = UpsPatch::load(&load_file_content(patch_path)).unwrap();
+        let target_content = load_file_content(target_path);
+        assert_eq!(patch.revert_no_check(&target_content).unwrap(), source_content);
+        assert_eq!(patch.revert(&target_content).unwrap(), source_content);
+    }
+}
+
+#[test]
+fn can_apply_in_both_directions() {
+    let source_content = load_file_content(SOURCE_PATH);
+    for (patch_path, target_path) in &[(PATCH_PATH_1, TARGET_PATH_1), (PATCH_PATH_2, TARGET_PATH_2)] {
+        let patch = UpsPatch::load(&load_file_content(patch_path)).unwrap();
+        let target_content = load_file_content(target_path);
+        assert_eq!(patch.direction_for(&source_content), Some(Direction::Forward));
+        assert_eq!(patch.direction_for(&target_content), Some(Direction::Reverse));
+        assert_eq!(patch.apply(&target_content).unwrap(), source_content);
+        assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+        assert_eq!(patch.apply_in_direction(&source_content, Direction::Forward).unwrap(), target_content);
+        assert_eq!(patch.apply_in_direction(&target_content, Direction::Reverse).unwrap(), source_content);
+    }
+}
+
+#[test]
+fn can_detect_patch_format() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let ips_content = IpsPatch::create(&source_content, &target_content).unwrap().get_patch_file_contents();
+    let bps_content = BpsPatch::create(&source_content, &target_content, &[]).get_patch_file_contents();
+    assert_eq!(detect(&load_file_content(PATCH_PATH_1)), Some(PatchFormat::Ups));
+    assert_eq!(detect(&ips_content), Some(PatchFormat::Ips));
+    assert_eq!(detect(&bps_content), Some(PatchFormat::Bps));
+    assert_eq!(detect(b"PPF30\x02"), Some(PatchFormat::Ppf));
+    assert_eq!(detect(&[0xD6, 0xC3, 0xC4, 0x00]), Some(PatchFormat::Vcdiff));
+    assert_eq!(detect(&source_content), None);
+}
+
+#[test]
+fn can_load_any_patch() {
+    let source_content = load_file_content(SOURCE_PATH);
+    let target_content = load_file_content(TARGET_PATH_1);
+    let patch_contents = vec![
+        (PatchFormat::Ups, load_file_content(PATCH_PATH_1)),
+        (PatchFormat::Ips, IpsPatch::create(&source_content, &target_content).unwrap().get_patch_file_contents()),
+        (PatchFormat::Bps, BpsPatch::create(&source_content, &target_content, &[]).get_patch_file_contents()),
+    ];
+    for (format, patch_content) in patch_contents {
+        let patch = load_any(&patch_content).unwrap();
+        assert_eq!(patch.format(), format);
+        assert!(patch.file_is_source(&source_content));
+        assert_eq!(patch.apply(&source_content).unwrap(), target_content);
+    }
+}